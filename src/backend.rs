@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Deserialize;
+use url::Url;
+
+use crate::interrupt::Handled;
+use crate::ollama::{Chat, ModelInfo, ModelSort, OutputFormat, PullStatus, Reply};
+use crate::render::Echo;
+
+/// Server APIs the chat can be run against
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /* Native Ollama API */
+    Ollama,
+    /* OpenAI compatible API (llama.cpp server, vLLM, LM Studio...) */
+    Openai,
+}
+
+/* Port of the server when `OLLAMA_HOST` has no scheme nor port */
+const DEFAULT_PORT: u16 = 11434;
+
+/// Base URL of the server from `OLLAMA_HOST` syntax
+///
+/// Without a scheme the host is reached over http on the Ollama port,
+/// with one the default port of the scheme is used. A path is kept as
+/// a prefix of all endpoints. An empty host, as in `:11434`, is the
+/// local machine.
+pub fn parse_host(host: &str) -> Result<Url> {
+    let host = host.trim();
+
+    let mut ret = if host.contains("://") {
+        Url::parse(host)
+    } else {
+        let host = if host.is_empty() || host.starts_with(':') {
+            format!("localhost{}", host)
+        } else {
+            host.to_string()
+        };
+        Url::parse(&format!("http://{}", host)).map(|mut u| {
+            if u.port().is_none() {
+                let _ = u.set_port(Some(DEFAULT_PORT));
+            }
+            u
+        })
+    }
+    .map_err(|e| anyhow!("Invalid host '{}' : {}", host, e))?;
+
+    if !matches!(ret.scheme(), "http" | "https") || ret.host().is_none() {
+        return Err(anyhow!("Invalid host '{}' : expected an http(s) URL", host));
+    }
+
+    /* Endpoints are joined relative to the base path */
+    if !ret.path().ends_with('/') {
+        let path = format!("{}/", ret.path());
+        ret.set_path(&path);
+    }
+
+    Ok(ret)
+}
+
+/// A chat server API
+///
+/// Backends stream answers, the turn logic (interruption, tool calls)
+/// is shared. Model management is only available on some servers.
+pub trait Backend {
+    /// Name used in error messages
+    fn name(&self) -> &str;
+
+    /// Server URL the endpoints are relative to
+    fn base(&self) -> &Url;
+
+    fn endpoint(&self, ep: &str) -> Url {
+        /* `ep` is relative, a join can not fail */
+        let ret = self.base().join(ep).expect("Invalid endpoint");
+        info!("Endpoint is {}", ret);
+        ret
+    }
+
+    fn model(&self) -> Option<&str>;
+
+    /// Select a model, it must be available on the server
+    fn set_model(&mut self, model: &str) -> Result<()>;
+
+    fn model_names(&self) -> Vec<String>;
+
+    /// Reload the list of available models
+    async fn refresh_models(&mut self) -> Result<()>;
+
+    fn print_models(
+        &self,
+        output: OutputFormat,
+        sort: ModelSort,
+        filter: Option<&str>,
+    ) -> Result<()>;
+
+    fn echo(&self) -> Echo;
+
+    /// How the response is printed while it is streamed
+    fn set_echo(&mut self, echo: Echo);
+
+    /// Stream the answer to `context` into `reply`
+    async fn stream(&self, context: &Chat, reply: &mut Reply) -> Result<()>;
+
+    /// Models can be pulled, removed, copied and shown
+    fn manages_models(&self) -> bool {
+        false
+    }
+
+    /// Download a model, `progress` is called for each status update
+    async fn pull(&self, _model: &str, _progress: impl FnMut(&PullStatus)) -> Result<()> {
+        Err(self.unsupported("Pulling models"))
+    }
+
+    async fn delete_model(&self, _model: &str) -> Result<()> {
+        Err(self.unsupported("Removing models"))
+    }
+
+    async fn copy_model(&self, _source: &str, _destination: &str) -> Result<()> {
+        Err(self.unsupported("Copying models"))
+    }
+
+    async fn show_model(&self, _model: &str) -> Result<ModelInfo> {
+        Err(self.unsupported("Showing models"))
+    }
+
+    fn unsupported(&self, what: &str) -> anyhow::Error {
+        anyhow!("{} is not supported by the {} backend", what, self.name())
+    }
+
+    fn context_new(&self) -> Result<Chat> {
+        match self.model() {
+            Some(model) => Ok(Chat::new(model)),
+            None => Err(anyhow!("No current model set")),
+        }
+    }
+
+    /// Send `prompt` (or the tool results) and record the answer
+    ///
+    /// Returns true when tools were called and their results must be
+    /// sent back with another call.
+    async fn chat(&self, prompt: Option<&str>, context: &mut Chat) -> Result<bool> {
+        /* Add user request */
+        if let Some(prompt) = prompt {
+            context.add_prompt(prompt);
+        }
+
+        let mut reply = Reply::new(self.echo());
+
+        /* Ctrl-C drops the request which stops the generation */
+        let _handled = Handled::new();
+        let truncated = tokio::select! {
+            ret = self.stream(context, &mut reply) => {
+                ret?;
+                false
+            }
+            _ = tokio::signal::ctrl_c() => true,
+        };
+
+        reply.finish()?;
+
+        Ok(context.end_turn(reply, truncated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_url(host: &str) -> String {
+        parse_host(host)
+            .unwrap()
+            .join("api/chat")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn bare_host() {
+        assert_eq!(
+            parse_host("example.com").unwrap().as_str(),
+            "http://example.com:11434/"
+        );
+        assert_eq!(chat_url("example.com"), "http://example.com:11434/api/chat");
+    }
+
+    #[test]
+    fn empty_host_is_localhost() {
+        assert_eq!(
+            parse_host(":11434").unwrap().as_str(),
+            "http://localhost:11434/"
+        );
+        assert_eq!(chat_url(":8080"), "http://localhost:8080/api/chat");
+        assert_eq!(chat_url(""), "http://localhost:11434/api/chat");
+    }
+
+    #[test]
+    fn https_with_port() {
+        assert_eq!(
+            parse_host("https://example.com:8443").unwrap().as_str(),
+            "https://example.com:8443/"
+        );
+        assert_eq!(
+            chat_url("https://example.com:8443"),
+            "https://example.com:8443/api/chat"
+        );
+        assert_eq!(
+            chat_url("https://example.com"),
+            "https://example.com/api/chat"
+        );
+    }
+
+    #[test]
+    fn ipv6_host() {
+        assert_eq!(
+            parse_host("[::1]:11434").unwrap().as_str(),
+            "http://[::1]:11434/"
+        );
+        assert_eq!(chat_url("[::1]"), "http://[::1]:11434/api/chat");
+    }
+
+    #[test]
+    fn path_prefix() {
+        assert_eq!(
+            parse_host("https://gw.example/ollama").unwrap().as_str(),
+            "https://gw.example/ollama/"
+        );
+        assert_eq!(
+            chat_url("https://gw.example/ollama"),
+            "https://gw.example/ollama/api/chat"
+        );
+        assert_eq!(
+            chat_url("https://gw.example/ollama/"),
+            "https://gw.example/ollama/api/chat"
+        );
+    }
+
+    #[test]
+    fn invalid_host() {
+        assert!(parse_host("ftp://example.com").is_err());
+        assert!(parse_host("http://").is_err());
+    }
+}
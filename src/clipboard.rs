@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use copypasta_ext::prelude::*;
+use log::info;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::{env, fmt};
+
+use crate::osc52::{self, Osc52};
+
+/// Clipboard implementations
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardBackend {
+    /* Detected from the session */
+    Auto,
+    /* wl-copy and wl-paste */
+    Wayland,
+    /* xclip or xsel */
+    X11,
+    /* Terminal escape sequence, write only */
+    Osc52,
+    /* Plain file in the data directory */
+    File,
+}
+
+impl fmt::Display for ClipboardBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ClipboardBackend::Auto => "auto",
+            ClipboardBackend::Wayland => "wayland",
+            ClipboardBackend::X11 => "x11",
+            ClipboardBackend::Osc52 => "osc52",
+            ClipboardBackend::File => "file",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Is `bin` an executable in `PATH`
+fn has_binary(bin: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(bin).is_file()))
+        .unwrap_or(false)
+}
+
+fn has_var(var: &str) -> bool {
+    env::var_os(var).is_some_and(|v| !v.is_empty())
+}
+
+impl ClipboardBackend {
+    /// Pick the clipboard reachable from this session
+    fn detect() -> ClipboardBackend {
+        if has_var("WAYLAND_DISPLAY") && has_binary("wl-copy") {
+            ClipboardBackend::Wayland
+        } else if has_var("DISPLAY") && (has_binary("xclip") || has_binary("xsel")) {
+            ClipboardBackend::X11
+        } else if (has_var("SSH_TTY") || has_var("SSH_CONNECTION")) && Osc52::available() {
+            /* The local terminal owns the clipboard */
+            ClipboardBackend::Osc52
+        } else {
+            ClipboardBackend::File
+        }
+    }
+}
+
+/// Clipboard of the user session
+pub struct Clipboard {
+    backend: ClipboardBackend,
+    osc52_max_bytes: usize,
+}
+
+impl Clipboard {
+    pub fn new(backend: ClipboardBackend) -> Clipboard {
+        let backend = match backend {
+            ClipboardBackend::Auto => ClipboardBackend::detect(),
+            b => b,
+        };
+
+        info!("Using the {} clipboard", backend);
+
+        Clipboard {
+            backend,
+            osc52_max_bytes: osc52::DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// Largest OSC 52 payload, longer texts are truncated
+    pub fn set_osc52_limit(&mut self, max_bytes: usize) {
+        self.osc52_max_bytes = max_bytes;
+    }
+
+    /// Whether the content can be read back, OSC 52 is write only
+    pub fn readable(&self) -> bool {
+        !matches!(self.backend, ClipboardBackend::Osc52)
+    }
+
+    fn file() -> Result<PathBuf> {
+        dirs::data_dir()
+            .map(|d| d.join("clippyrs").join("clipboard.txt"))
+            .ok_or(anyhow!("No data directory for the clipboard file"))
+    }
+
+    /// Read the clipboard content
+    pub fn get(&self) -> Result<String> {
+        let ret = match self.backend {
+            ClipboardBackend::Wayland => copypasta_ext::wayland_bin::ClipboardContext::new()
+                .and_then(|mut ctx| ctx.get_contents()),
+            ClipboardBackend::X11 => copypasta_ext::x11_bin::ClipboardContext::new()
+                .and_then(|mut ctx| ctx.get_contents()),
+            ClipboardBackend::Osc52 => {
+                return Err(anyhow!(
+                    "The osc52 clipboard can only be written, use --clipboard-backend to read another one"
+                ))
+            }
+            /* Auto is resolved when created */
+            ClipboardBackend::File | ClipboardBackend::Auto => {
+                let path = Clipboard::file()?;
+                return fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read clipboard file {}", path.display()));
+            }
+        };
+
+        ret.map_err(|e| anyhow!("Failed to read the {} clipboard : {}", self.backend, e))
+    }
+
+    /// Replace the clipboard content
+    pub fn set(&self, text: &str) -> Result<()> {
+        let ret = match self.backend {
+            ClipboardBackend::Wayland => copypasta_ext::wayland_bin::ClipboardContext::new()
+                .and_then(|mut ctx| ctx.set_contents(text.to_string())),
+            ClipboardBackend::X11 => copypasta_ext::x11_bin::ClipboardContext::new()
+                .and_then(|mut ctx| ctx.set_contents(text.to_string())),
+            ClipboardBackend::Osc52 => {
+                let copied = Osc52::new(self.osc52_max_bytes).copy(text)?;
+                if copied.truncated() {
+                    eprintln!(
+                        "{} clipboard truncated to {} of {} bytes (OSC 52 limit)",
+                        "Warning:".bold().yellow(),
+                        copied.copied,
+                        copied.total
+                    );
+                }
+                return Ok(());
+            }
+            /* Auto is resolved when created */
+            ClipboardBackend::File | ClipboardBackend::Auto => {
+                let path = Clipboard::file()?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                return fs::write(&path, text)
+                    .with_context(|| format!("Failed to write clipboard file {}", path.display()));
+            }
+        };
+
+        ret.map_err(|e| anyhow!("Failed to write the {} clipboard : {}", self.backend, e))
+    }
+}
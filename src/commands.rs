@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+
+/// Slash commands available in interactive mode
+pub enum SlashCommand {
+    Model(String),
+    Clear,
+    Undo,
+    Retry,
+    Save(Option<String>),
+    Load(String),
+    System(String),
+    Tools(bool),
+    Copy,
+    Stats,
+    Help,
+}
+
+/* Name, arguments and description used for help and completion */
+pub const COMMANDS: [(&str, &str, &str); 11] = [
+    ("/model", "<name>", "Switch to another model"),
+    ("/clear", "", "Clear all but the system prompt"),
+    ("/undo", "", "Drop the last exchange"),
+    ("/retry", "", "Regenerate the last answer"),
+    ("/save", "[name]", "Save the conversation as a session"),
+    ("/load", "<name>", "Load a saved session"),
+    ("/system", "<text>", "Set the system prompt"),
+    ("/tools", "on|off", "Enable or disable tools"),
+    ("/copy", "", "Copy the last answer to the clipboard"),
+    ("/stats", "", "Show token usage of the conversation"),
+    ("/help", "", "Show this help"),
+];
+
+impl SlashCommand {
+    /// Parse a slash command, `None` if the line is not a command
+    pub fn parse(line: &str) -> Option<Result<SlashCommand>> {
+        let line = line.trim();
+
+        if !line.starts_with('/') {
+            return None;
+        }
+
+        let (cmd, arg) = match line.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+
+        let need_arg = |what: &str| -> Result<String> {
+            if arg.is_empty() {
+                Err(anyhow!("{} expects {}", cmd, what))
+            } else {
+                Ok(arg.to_string())
+            }
+        };
+
+        let ret = match cmd {
+            "/model" => need_arg("a model name").map(SlashCommand::Model),
+            "/clear" => Ok(SlashCommand::Clear),
+            "/undo" => Ok(SlashCommand::Undo),
+            "/retry" => Ok(SlashCommand::Retry),
+            "/save" => Ok(SlashCommand::Save(need_arg("a name").ok())),
+            "/load" => need_arg("a session name").map(SlashCommand::Load),
+            "/system" => need_arg("a prompt").map(SlashCommand::System),
+            "/tools" => match arg {
+                "on" => Ok(SlashCommand::Tools(true)),
+                "off" => Ok(SlashCommand::Tools(false)),
+                _ => Err(anyhow!("/tools expects on or off")),
+            },
+            "/copy" => Ok(SlashCommand::Copy),
+            "/stats" => Ok(SlashCommand::Stats),
+            "/help" => Ok(SlashCommand::Help),
+            _ => Err(anyhow!("Unknown command {}, see /help", cmd)),
+        };
+
+        Some(ret)
+    }
+
+    pub fn help() -> String {
+        COMMANDS
+            .iter()
+            .map(|(name, args, desc)| format!("{:<8} {:<8} {}", name, args, desc))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
@@ -0,0 +1,441 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs};
+
+use crate::backend::BackendKind;
+use crate::clipboard::ClipboardBackend;
+use crate::ollama::Options;
+use crate::render::Render;
+
+/* Persona in the configuration file
+
+[personas.translator]
+system = "Translate everything the user says to english."
+model = "mistral"
+tools = ["math_calculator"]
+options = { temperature = 0.2 }
+*/
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Persona {
+    /// System prompt set at the start of the chat
+    pub system: Option<String>,
+    /// Model used when none is given on the command line
+    pub model: Option<String>,
+    /// Tools to enable by name
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Generation options forwarded to the model
+    #[serde(default)]
+    pub options: Options,
+}
+
+/* Recipe in the configuration file, run with `clippyrs run typos`
+
+[recipes.typos]
+prompt = "Fix typos in this text: ::CL::"
+description = "Fix typos in the clipboard"
+model = "mistral"
+store_in_clipboard = true
+
+[recipes.translate]
+prompt = "Translate to {{1}}: {{2}}"
+*/
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    /// Prompt with `{{1}}`, `{{2}}`... slots for the arguments
+    pub prompt: String,
+    /// Shown by `clippyrs recipes list`
+    pub description: Option<String>,
+    pub model: Option<String>,
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub store_in_clipboard: bool,
+    #[serde(default)]
+    pub force_md: bool,
+    #[serde(default)]
+    pub options: Options,
+}
+
+impl Recipe {
+    /// Byte range and number of each `{{n}}` slot of the prompt
+    fn slots(&self) -> Vec<(Range<usize>, usize)> {
+        let mut ret = Vec::new();
+        let mut from = 0;
+
+        while let Some(start) = self.prompt[from..].find("{{").map(|s| s + from) {
+            let after = start + 2;
+            let slot = self.prompt[after..].find("}}").and_then(|end| {
+                self.prompt[after..after + end]
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(|n| (n, after + end + 2))
+            });
+
+            match slot {
+                Some((n, end)) => {
+                    ret.push((start..end, n));
+                    from = end;
+                }
+                None => from = after,
+            }
+        }
+
+        ret
+    }
+
+    /// Number of arguments, the highest slot number
+    pub fn arity(&self) -> usize {
+        self.slots().iter().map(|(_, n)| *n).max().unwrap_or(0)
+    }
+
+    /// Prompt with each `{{n}}` slot replaced by the n-th argument
+    ///
+    /// Extra arguments are joined into the last slot, so that
+    /// `translate french good morning` needs no quotes.
+    pub fn fill(&self, args: &[String]) -> Result<String> {
+        let arity = self.arity();
+
+        if args.len() < arity || (arity == 0 && !args.is_empty()) {
+            return Err(anyhow!(
+                "The recipe takes {} arguments but got {}",
+                arity,
+                args.len()
+            ));
+        }
+
+        let mut args = args.to_vec();
+        if arity > 0 {
+            let rest = args.split_off(arity - 1).join(" ");
+            args.push(rest);
+        }
+
+        let mut ret = String::new();
+        let mut last = 0;
+
+        for (range, n) in self.slots() {
+            ret += &self.prompt[last..range.start];
+            ret += &args[n - 1];
+            last = range.end;
+        }
+
+        ret += &self.prompt[last..];
+
+        Ok(ret)
+    }
+}
+
+/* Skin colours as ANSI values
+
+[skin]
+headers = 178
+scrollbar = 178
+bold = 208
+*/
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SkinConfig {
+    pub headers: u8,
+    pub scrollbar: u8,
+    pub bold: Option<u8>,
+    pub italic: Option<u8>,
+    pub inline_code: Option<u8>,
+}
+
+impl Default for SkinConfig {
+    fn default() -> SkinConfig {
+        SkinConfig {
+            headers: 178,
+            scrollbar: 178,
+            bold: None,
+            italic: None,
+            inline_code: None,
+        }
+    }
+}
+
+/* Connection to the server, for remote instances behind a gateway
+
+[http]
+api_key = "secret"
+headers = { "X-Team" = "research" }
+ca_bundle = "/etc/ssl/gateway-ca.pem"
+client_cert = "/etc/ssl/me.pem"
+client_key = "/etc/ssl/me.key"
+insecure = false
+proxy = "http://proxy:3128"
+connect_timeout = 5
+read_timeout = 300
+*/
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Sent as a bearer token, overriden by `OLLAMA_API_KEY`
+    pub api_key: Option<String>,
+    /// Extra headers sent with every request
+    pub headers: HashMap<String, String>,
+    /// PEM certificates trusted in addition to the system ones
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM client certificate, with its key unless `client_key` is set
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM key of the client certificate
+    pub client_key: Option<PathBuf>,
+    /// Accept invalid server certificates
+    pub insecure: bool,
+    /// Proxy for all requests, "none" ignores the `*_PROXY` variables
+    pub proxy: Option<String>,
+    /// Seconds to establish a connection
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for each read of a response
+    pub read_timeout: Option<u64>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+impl HttpConfig {
+    /// HTTP client shared by all requests to the server
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in self.headers.iter() {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("Invalid header name '{}'", name))?,
+                HeaderValue::from_str(value)
+                    .with_context(|| format!("Invalid value for header '{}'", name))?,
+            );
+        }
+
+        if let Some(key) = env::var("OLLAMA_API_KEY").ok().or(self.api_key.clone()) {
+            let mut value =
+                HeaderValue::from_str(&format!("Bearer {}", key)).context("Invalid API key")?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .danger_accept_invalid_certs(self.insecure);
+
+        if let Some(path) = &self.ca_bundle {
+            let certs = reqwest::Certificate::from_pem_bundle(&read_file(path)?)
+                .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let Some(cert) = &self.client_cert {
+            let key = self.client_key.as_ref().unwrap_or(cert);
+            let identity =
+                reqwest::Identity::from_pkcs8_pem(&read_file(cert)?, &read_file(key)?)
+                    .with_context(|| format!("Invalid client certificate {}", cert.display()))?;
+            builder = builder.identity(identity);
+        }
+
+        match self.proxy.as_deref() {
+            Some("none") => builder = builder.no_proxy(),
+            Some(proxy) => {
+                builder = builder.proxy(
+                    reqwest::Proxy::all(proxy)
+                        .with_context(|| format!("Invalid proxy '{}'", proxy))?,
+                )
+            }
+            None => {}
+        }
+
+        if let Some(secs) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+
+        if let Some(secs) = self.read_timeout {
+            builder = builder.read_timeout(Duration::from_secs(secs));
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/* Configuration file (~/.config/clippyrs/config.toml)
+
+host = "http://localhost:11434"
+backend = "ollama"
+model = "mistral"
+force_md = false
+render = "inline"
+enable_tools = false
+store_in_clipboard = false
+clipboard_backend = "auto"
+osc52_max_bytes = 100000
+tools = ["math_calculator"]
+
+[options]
+temperature = 0.7
+
+[http]
+...
+
+[skin]
+...
+
+[personas.name]
+...
+
+[recipes.name]
+...
+*/
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Server URL, overriden by `OLLAMA_HOST` and `--host`
+    pub host: Option<String>,
+    /// Server API, overriden by `--backend`
+    pub backend: Option<BackendKind>,
+    /// Default model
+    pub model: Option<String>,
+    pub force_md: bool,
+    /// Overriden by `CLIPPYRS_ENABLE_TOOLS` and `--enable-tools`
+    pub enable_tools: Option<bool>,
+    /// Overriden by `CLIPPYRS_STORE_IN_CLIPBOARD` and `--store-in-clipboard`
+    pub store_in_clipboard: Option<bool>,
+    /// How answers are displayed, overriden by `--render`
+    pub render: Option<Render>,
+    /// Clipboard to use, overriden by `--clipboard-backend`
+    pub clipboard_backend: Option<ClipboardBackend>,
+    /// Largest OSC 52 clipboard payload
+    pub osc52_max_bytes: Option<usize>,
+    /// Tools enabled by name when the persona does not list any, even
+    /// with `enable_tools = false`, only `--no-enable-tools` drops them
+    pub tools: Vec<String>,
+    /// Default generation options
+    pub options: Options,
+    pub http: HttpConfig,
+    pub skin: SkinConfig,
+    personas: HashMap<String, Persona>,
+    recipes: BTreeMap<String, Recipe>,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("clippyrs").join("config.toml"))
+    }
+
+    /// Load the user configuration, a missing default file gives the defaults
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Config::path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        info!("Loading configuration from {}", path.display());
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read configuration {}", path.display()))?;
+        let config: Config = toml::from_str(&data)
+            .with_context(|| format!("Failed to parse configuration {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    pub fn persona(&self, name: &str) -> Result<Persona> {
+        self.personas.get(name).cloned().ok_or(anyhow!(
+            "No such persona '{}' available personas are {:?}",
+            name,
+            self.personas.keys().collect::<Vec<&String>>()
+        ))
+    }
+
+    pub fn recipe(&self, name: &str) -> Result<Recipe> {
+        self.recipes.get(name).cloned().ok_or(anyhow!(
+            "No such recipe '{}' available recipes are {:?}",
+            name,
+            self.recipes.keys().collect::<Vec<&String>>()
+        ))
+    }
+
+    pub fn recipes(&self) -> &BTreeMap<String, Recipe> {
+        &self.recipes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(prompt: &str) -> Recipe {
+        Recipe {
+            prompt: prompt.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn spaced_slot() {
+        let r = recipe("Translate to {{ 2 }}: {{1}}");
+
+        assert_eq!(r.arity(), 2);
+        assert_eq!(
+            r.fill(&args(&["hello", "french"])).unwrap(),
+            "Translate to french: hello"
+        );
+    }
+
+    #[test]
+    fn repeated_slot() {
+        let r = recipe("{{1}} and {{1}} again");
+
+        assert_eq!(r.arity(), 1);
+        assert_eq!(r.fill(&args(&["this"])).unwrap(), "this and this again");
+    }
+
+    #[test]
+    fn other_braces_kept() {
+        let r = recipe("{{x}} {{0}} {{1}} {{ }} {{2");
+
+        assert_eq!(r.arity(), 1);
+        assert_eq!(r.fill(&args(&["é"])).unwrap(), "{{x}} {{0}} é {{ }} {{2");
+    }
+
+    #[test]
+    fn extra_arguments_go_to_last_slot() {
+        let r = recipe("Translate to {{1}}: {{2}}");
+
+        assert_eq!(
+            r.fill(&args(&["french", "good", "morning"])).unwrap(),
+            "Translate to french: good morning"
+        );
+    }
+
+    #[test]
+    fn arity_errors() {
+        let r = recipe("Translate to {{1}}: {{2}}");
+        assert!(r.fill(&args(&["french"])).is_err());
+        assert!(r.fill(&[]).is_err());
+
+        /* Missing slots still count */
+        assert_eq!(recipe("{{3}}").arity(), 3);
+        assert!(recipe("{{3}}").fill(&args(&["a", "b"])).is_err());
+
+        let r = recipe("No slots");
+        assert_eq!(r.fill(&[]).unwrap(), "No slots");
+        assert!(r.fill(&args(&["extra"])).is_err());
+    }
+}
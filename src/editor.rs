@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use log::warn;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, Helper, Hinter,
+    KeyCode, KeyEvent, Modifiers, RepeatCount,
+};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs, process};
+
+/* Multi-line blocks are delimited by triple quotes */
+const BLOCK: &str = "\"\"\"";
+
+#[derive(Helper, Hinter)]
+struct InputHelper {
+    /* Commands with their argument candidates */
+    completions: BTreeMap<String, Vec<String>>,
+}
+
+impl Completer for InputHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+
+        let ret = match line.split_once(' ') {
+            None => (
+                0,
+                self.completions
+                    .keys()
+                    .filter(|c| c.starts_with(line))
+                    .cloned()
+                    .collect(),
+            ),
+            Some((cmd, arg)) => (
+                pos - arg.len(),
+                self.completions
+                    .get(cmd)
+                    .iter()
+                    .flat_map(|v| v.iter())
+                    .filter(|v| v.starts_with(arg))
+                    .cloned()
+                    .collect(),
+            ),
+        };
+
+        Ok(ret)
+    }
+}
+
+impl Highlighter for InputHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        _default: bool,
+    ) -> Cow<'b, str> {
+        Cow::Owned(prompt.bold().blue().to_string())
+    }
+}
+
+impl Validator for InputHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+
+        /* Trailing backslash continues on next line */
+        if input.ends_with('\\') {
+            return Ok(ValidationResult::Incomplete);
+        }
+
+        /* Unterminated triple quote block */
+        if input.trim_start().starts_with(BLOCK) && input.matches(BLOCK).count() % 2 == 1 {
+            return Ok(ValidationResult::Incomplete);
+        }
+
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+/* Ctrl-C at an empty prompt exits, otherwise it drops the line */
+struct InterruptHandler {}
+
+impl ConditionalEventHandler for InterruptHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        if ctx.line().is_empty() {
+            Some(Cmd::EndOfFile)
+        } else {
+            None
+        }
+    }
+}
+
+/// Interactive prompt reader with persistent history and multi-line input
+pub struct LineEditor {
+    rl: Editor<InputHelper, DefaultHistory>,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new() -> Result<LineEditor> {
+        let mut rl: Editor<InputHelper, DefaultHistory> = Editor::new()?;
+        rl.set_helper(Some(InputHelper {
+            completions: BTreeMap::new(),
+        }));
+        /* Alt-Enter inserts a new line */
+        rl.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), Cmd::Newline);
+        rl.bind_sequence(
+            KeyEvent::ctrl('C'),
+            EventHandler::Conditional(Box::new(InterruptHandler {})),
+        );
+
+        let history = dirs::data_dir().map(|d| d.join("clippyrs").join("history.txt"));
+
+        if let Some(path) = &history {
+            if path.exists() {
+                if let Err(e) = rl.load_history(path) {
+                    warn!("Failed to load history {} : {}", path.display(), e);
+                }
+            }
+        }
+
+        Ok(LineEditor { rl, history })
+    }
+
+    /// Complete `command` and its arguments from `args` with Tab
+    pub fn add_completion(&mut self, command: &str, args: Vec<String>) {
+        if let Some(helper) = self.rl.helper_mut() {
+            helper.completions.insert(command.to_string(), args);
+        }
+    }
+
+    /// Read the next prompt, `None` at end of input
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>> {
+        let line = match self.rl.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof) => return Ok(None),
+            /* Ctrl-C on a non empty line drops it */
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !line.trim().is_empty() {
+            self.rl.add_history_entry(line.as_str())?;
+            self.save_history();
+        }
+
+        /* "\e" alone or at the end of the text opens the editor */
+        if let Some(text) = line.strip_suffix("\\e") {
+            return Ok(Some(compose_in_editor(text)?));
+        }
+
+        Ok(Some(unfold_multiline(&line)))
+    }
+
+    fn save_history(&mut self) {
+        if let Some(path) = &self.history {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+
+            if let Err(e) = self.rl.save_history(path) {
+                warn!("Failed to save history {} : {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Remove line continuations and triple quote delimiters
+fn unfold_multiline(line: &str) -> String {
+    let ret = line.replace("\\\n", "\n");
+    let trimmed = ret.trim();
+
+    if let Some(inner) = trimmed
+        .strip_prefix(BLOCK)
+        .and_then(|s| s.strip_suffix(BLOCK))
+    {
+        return inner.trim_matches('\n').to_string();
+    }
+
+    ret
+}
+
+/// Ask a yes/no question on the terminal, even when stdin is piped
+pub fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().is_terminal() {
+        io::stdin().read_line(&mut answer)?;
+    } else {
+        let tty = File::open("/dev/tty").context("No terminal to confirm")?;
+        BufReader::new(tty).read_line(&mut answer)?;
+    }
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Compose a prompt in `$VISUAL` or `$EDITOR` starting from `initial`
+pub fn compose_in_editor(initial: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or(env::var("EDITOR"))
+        .unwrap_or("vi".to_string());
+
+    let path = env::temp_dir().join(format!("clippyrs-{}.md", process::id()));
+    fs::write(&path, initial)?;
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor))?;
+
+    let text = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    if !status.success() {
+        return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+    }
+
+    Ok(text?.trim_end().to_string())
+}
@@ -0,0 +1,182 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::{fmt, io};
+
+/* Error body returned by the API
+{
+  "error": "model 'foo' not found, try pulling it first"
+}
+or by OpenAI compatible servers
+{
+  "error": { "message": "model 'foo' not found", "type": "invalid_request_error" }
+}
+*/
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ErrorMessage {
+    Text(String),
+    Object { message: String },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorBody {
+    error: ErrorMessage,
+}
+
+impl ErrorBody {
+    pub fn message(self) -> String {
+        match self.error {
+            ErrorMessage::Text(msg) | ErrorMessage::Object { message: msg } => msg,
+        }
+    }
+}
+
+/// Failures when talking to the server API
+#[derive(Debug)]
+pub enum ApiError {
+    /// Server could not be reached
+    Connection { url: String, source: reqwest::Error },
+    /// Request did not complete in time
+    Timeout(String),
+    /// Requested model does not exist on the server
+    ModelNotFound(String),
+    /// Server answered with an error status
+    Server { status: u16, message: String },
+    /// Response stream ended abnormally
+    StreamInterrupted(String),
+    /// Any other transport failure
+    Other(String),
+}
+
+impl ApiError {
+    /// Process exit code reported for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ApiError::Connection { .. } => 3,
+            ApiError::Timeout(_) => 4,
+            ApiError::ModelNotFound(_) => 5,
+            ApiError::Server { .. } => 6,
+            ApiError::StreamInterrupted(_) => 7,
+            ApiError::Other(_) => 1,
+        }
+    }
+
+    /// Turn an error status into an `ApiError` using the body message
+    pub async fn check(resp: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+        let status = resp.status();
+
+        if status.is_success() {
+            return Ok(resp);
+        }
+
+        let body = resp.text().await.unwrap_or_default();
+        let message = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(b) => b.message(),
+            Err(_) if body.trim().is_empty() => status.to_string(),
+            Err(_) => body.trim().to_string(),
+        };
+
+        /* A wrong path prefix is also a 404, only the body tells a missing model */
+        if status == reqwest::StatusCode::NOT_FOUND && message.to_lowercase().contains("model") {
+            Err(ApiError::ModelNotFound(message))
+        } else {
+            Err(ApiError::Server {
+                status: status.as_u16(),
+                message,
+            })
+        }
+    }
+
+    /// Classify an error raised while reading a response body
+    pub fn from_stream(e: reqwest::Error) -> ApiError {
+        if e.is_timeout() {
+            ApiError::Timeout(e.to_string())
+        } else {
+            ApiError::StreamInterrupted(e.to_string())
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> ApiError {
+        let url = e.url().map(|u| u.to_string()).unwrap_or_default();
+
+        if e.is_timeout() {
+            ApiError::Timeout(url)
+        } else if e.is_connect() {
+            ApiError::Connection { url, source: e }
+        } else if e.is_body() || e.is_decode() {
+            ApiError::StreamInterrupted(e.to_string())
+        } else if let Some(status) = e.status() {
+            ApiError::Server {
+                status: status.as_u16(),
+                message: e.to_string(),
+            }
+        } else {
+            ApiError::Other(causes(&e))
+        }
+    }
+}
+
+/// Message of `e` followed by its causes
+fn causes(e: &dyn Error) -> String {
+    let mut ret = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        ret += &format!(" : {}", s);
+        source = s.source();
+    }
+    ret
+}
+
+/// Innermost cause of `e`, reqwest messages repeat the URL
+fn root_cause(e: &dyn Error) -> String {
+    let mut ret = e;
+    while let Some(s) = ret.source() {
+        ret = s;
+    }
+    ret.to_string()
+}
+
+/// Nothing listens on the server port
+fn refused(e: &reqwest::Error) -> bool {
+    let mut source = e.source();
+    while let Some(s) = source {
+        if s.downcast_ref::<io::Error>()
+            .is_some_and(|io| io.kind() == io::ErrorKind::ConnectionRefused)
+        {
+            return true;
+        }
+        source = s.source();
+    }
+    false
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Connection { url, source } => {
+                write!(
+                    f,
+                    "Cannot connect to the server at {} : {}",
+                    url,
+                    root_cause(source)
+                )?;
+                if refused(source) {
+                    write!(f, " (is it running ?)")?;
+                }
+                Ok(())
+            }
+            ApiError::Timeout(url) => write!(f, "Request to {} timed out", url),
+            ApiError::ModelNotFound(msg) => write!(f, "Model not found : {}", msg),
+            ApiError::Server { status, message } => {
+                write!(f, "Server error ({}) : {}", status, message)
+            }
+            ApiError::StreamInterrupted(msg) => write!(f, "Response stream interrupted : {}", msg),
+            ApiError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/* The cause is part of the message, it is not chained again */
+impl Error for ApiError {}
@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Context, Result};
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Build the `format` field of a chat request, either "json" or a JSON schema
+pub fn from_args(json: bool, schema: Option<&Path>) -> Result<Option<Value>> {
+    if let Some(path) = schema {
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read schema {}", path.display()))?;
+        let schema: Value = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse schema {}", path.display()))?;
+
+        /* Make sure the schema is usable before querying the model */
+        JSONSchema::compile(&schema)
+            .map_err(|e| anyhow!("Invalid schema {} : {}", path.display(), e))?;
+
+        Ok(Some(schema))
+    } else if json {
+        Ok(Some(Value::String("json".to_string())))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Check that a response parses as JSON and matches the schema if any
+pub fn check(format: &Value, response: &str) -> Result<Value> {
+    let value: Value = serde_json::from_str(response.trim())
+        .map_err(|e| anyhow!("Response is not valid JSON : {}", e))?;
+
+    if format.is_object() {
+        let schema = JSONSchema::compile(format).map_err(|e| anyhow!("Invalid schema : {}", e))?;
+
+        let errors: Vec<String> = match schema.validate(&value) {
+            Ok(_) => vec![],
+            Err(errors) => errors
+                .map(|e| format!("{} at '{}'", e, e.instance_path))
+                .collect(),
+        };
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "Response does not match the schema : {}",
+                errors.join(", ")
+            ));
+        }
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,36 @@
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/* Listening to Ctrl-C replaces the default SIGINT action for the whole
+process, steps which do not listen to it themselves exit as before */
+static HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit status of a process killed by SIGINT
+const SIGINT_STATUS: i32 = 130;
+
+/// Ctrl-C is handled by the caller while this is alive
+pub struct Handled;
+
+impl Handled {
+    pub fn new() -> Handled {
+        HANDLERS.fetch_add(1, Ordering::SeqCst);
+        Handled
+    }
+}
+
+impl Drop for Handled {
+    fn drop(&mut self) {
+        HANDLERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Exit on Ctrl-C unless a `Handled` guard is alive
+pub fn exit_when_unhandled() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if HANDLERS.load(Ordering::SeqCst) == 0 {
+                process::exit(SIGINT_STATUS);
+            }
+        }
+    });
+}
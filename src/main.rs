@@ -1,10 +1,24 @@
-use anyhow::Result;
-use copypasta_ext::prelude::*;
-use copypasta_ext::x11_bin::ClipboardContext;
-use log::{error, info};
-use ollama::{Ollama, Tool};
+use anyhow::{anyhow, Context, Result};
+use backend::{Backend, BackendKind};
+use clipboard::{Clipboard, ClipboardBackend};
+use commands::{SlashCommand, COMMANDS};
+use config::{Config, Persona, Recipe, SkinConfig};
+use editor::{confirm, LineEditor};
+use error::ApiError;
+use log::{error, info, warn};
+use ollama::{Chat, ModelSort, Ollama, Options, OutputFormat, Stats, Tool};
+use openai::OpenAi;
+use render::{Echo, Render};
+use session::SessionStore;
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::stdout;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+use table::{human_size, print_table};
+use template::Unfolder;
 use termimad::crossterm::style::Color::*;
 use termimad::crossterm::{
     cursor::{Hide, Show},
@@ -13,18 +27,53 @@ use termimad::crossterm::{
     terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use termimad::*;
+mod backend;
+mod clipboard;
+mod commands;
+mod config;
+mod editor;
+mod error;
+mod format;
+mod interrupt;
+mod ndjson;
 mod ollama;
-use clap::Parser;
+mod openai;
+mod osc52;
+mod render;
+mod session;
+mod sse;
+mod table;
+mod template;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 
-fn user_prompt() {
-    print!("{}", "\nUser: ".bold().blue());
+fn assistant_prompt<B: Backend>(ollama: &B) {
+    print!("{}", "\nAssistant: ".bold().red());
+    /* Rendered markdown starts on its own line */
+    if matches!(ollama.echo(), Echo::Markdown(_)) {
+        println!();
+    }
     io::stdout().flush().unwrap();
 }
 
-fn assistant_prompt() {
-    print!("{}", "\nAssistant: ".bold().red());
-    io::stdout().flush().unwrap();
+fn make_skin(conf: &SkinConfig) -> MadSkin {
+    let mut skin = MadSkin::default();
+    skin.table.align = Alignment::Center;
+    skin.set_headers_fg(AnsiValue(conf.headers));
+    skin.scrollbar.thumb.set_fg(AnsiValue(conf.scrollbar));
+    skin.code_block.align = Alignment::Center;
+
+    if let Some(c) = conf.bold {
+        skin.bold.set_fg(AnsiValue(c));
+    }
+    if let Some(c) = conf.italic {
+        skin.italic.set_fg(AnsiValue(c));
+    }
+    if let Some(c) = conf.inline_code {
+        skin.inline_code.set_fg(AnsiValue(c));
+    }
+
+    skin
 }
 
 fn view_area() -> Area {
@@ -67,68 +116,631 @@ fn view_resp(skin: MadSkin, md: String) -> Result<(), Error> {
     Ok(())
 }
 
-fn prompt_unfold_vars(prompt: String) -> Result<String> {
+fn prompt_unfold_vars(prompt: String, args: &Args) -> Result<String> {
+    Unfolder::with_defaults(!args.yes, clipboard_backend(args)).unfold(&prompt)
+}
+
+/// Insert piped input at `::STDIN::` (or append it) and attach files
+fn prompt_attach_input(prompt: String, stdin: Option<String>, files: &[PathBuf]) -> Result<String> {
     let mut ret = prompt;
 
-    /* Input clipboard */
-    if ret.contains("::CL::") {
-        if let Ok(mut ctx) = ClipboardContext::new() {
-            let clipboard_data = ctx.get_contents().unwrap_or("".to_string());
-            ret = ret.replace("::CL::", &clipboard_data);
+    if let Some(input) = stdin {
+        if ret.contains("::STDIN::") {
+            ret = ret.replace("::STDIN::", &input);
+        } else if ret.is_empty() {
+            ret = input;
+        } else {
+            ret = format!("{}\n\n{}", ret, input);
         }
     }
 
+    for path in files.iter() {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let lang = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+        ret += &format!(
+            "\n\n`{}`:\n```{}\n{}\n```",
+            path.display(),
+            lang,
+            content.trim_end()
+        );
+    }
+
+    if ret.trim().is_empty() {
+        return Err(anyhow!("Empty prompt"));
+    }
+
     Ok(ret)
 }
 
-fn store_in_clipboard(response: String) {
-    if let Ok(mut ctx) = ClipboardContext::new() {
-        if let Err(e) = ctx.set_contents(response) {
-            error!("Failed to write content to clipboard: {}", e);
-        } else {
-            info!("Did set output data to clipboard");
+fn print_stats(label: &str, stats: &Stats) {
+    eprintln!("{} {}", format!("[{}]", label).dimmed(), stats);
+}
+
+fn resolve_flag(flag: Option<bool>, no_flag: bool, file: Option<bool>) -> bool {
+    !no_flag && flag.or(file).unwrap_or(false)
+}
+
+fn clipboard_backend(args: &Args) -> ClipboardBackend {
+    args.clipboard_backend.unwrap_or(ClipboardBackend::Auto)
+}
+
+fn store_in_clipboard(args: &Args, response: &str) -> Result<()> {
+    let mut clipboard = Clipboard::new(clipboard_backend(args));
+    if let Some(max) = args.osc52_max_bytes {
+        clipboard.set_osc52_limit(max);
+    }
+    clipboard.set(response)?;
+    info!("Did set output data to clipboard");
+    Ok(())
+}
+
+fn print_recipes(config: &Config) {
+    let mut rows: Vec<[String; 3]> = vec![[
+        "NAME".to_string(),
+        "MODEL".to_string(),
+        "DESCRIPTION".to_string(),
+    ]];
+
+    for (name, recipe) in config.recipes() {
+        /* The prompt tells what a recipe without description does */
+        let description = recipe
+            .description
+            .clone()
+            .unwrap_or(recipe.prompt.lines().next().unwrap_or_default().to_string());
+        rows.push([
+            name.clone(),
+            recipe.model.clone().unwrap_or_default(),
+            description,
+        ]);
+    }
+
+    print_table(&rows);
+}
+
+/// Recipe and its prompt for a `/name args` line, `None` if not a recipe
+fn recipe_call<'a>(
+    line: &str,
+    recipes: &'a BTreeMap<String, Recipe>,
+) -> Option<Result<(&'a Recipe, String)>> {
+    let line = line.trim().strip_prefix('/')?;
+
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg),
+        None => (line, ""),
+    };
+
+    /* Builtin commands take precedence */
+    if COMMANDS.iter().any(|(cmd, _, _)| cmd[1..] == *name) {
+        return None;
+    }
+
+    let recipe = recipes.get(name)?;
+
+    /* Arguments are words as on the command line */
+    let values: Vec<String> = arg.split_whitespace().map(str::to_string).collect();
+
+    Some(recipe.fill(&values).map(|prompt| (recipe, prompt)))
+}
+
+/// Chat settings a recipe overrides for one turn
+struct TurnSettings {
+    model: String,
+    system: Option<String>,
+    options: Options,
+    tools: Vec<String>,
+}
+
+impl TurnSettings {
+    fn save(chat: &Chat) -> TurnSettings {
+        TurnSettings {
+            model: chat.model().to_string(),
+            system: chat.system().map(str::to_string),
+            options: chat.options().clone(),
+            tools: chat.tools().iter().map(|t| t.name().to_string()).collect(),
         }
-    } else {
-        error!("No ctx");
     }
+
+    fn restore<B: Backend>(self, ollama: &mut B, chat: &mut Chat) -> Result<()> {
+        if chat.model() != self.model {
+            ollama.set_model(&self.model)?;
+            chat.set_model(&self.model);
+        }
+
+        match &self.system {
+            Some(system) => chat.set_system(system),
+            None => chat.clear_system(),
+        }
+        chat.set_options(self.options);
+
+        chat.clear_tools();
+        for tool in self.tools.iter().filter_map(|name| Tool::by_name(name)) {
+            chat.add_tool(tool);
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply the model, persona and options of a recipe to `chat`
+///
+/// As with `clippyrs run` the command line ones win.
+async fn recipe_apply<B: Backend>(
+    ollama: &mut B,
+    chat: &mut Chat,
+    args: &Args,
+    config: &Config,
+    recipe: &Recipe,
+) -> Result<()> {
+    let persona = match (&args.persona, &recipe.persona) {
+        (None, Some(name)) => Some(config.persona(name)?),
+        _ => None,
+    };
+
+    /* Model precedence is CLI (or env), recipe then persona */
+    let model = recipe
+        .model
+        .as_ref()
+        .or(persona.as_ref().and_then(|p| p.model.as_ref()));
+    if let Some(model) = model.filter(|_| args.model.is_none()) {
+        select_model(ollama, model).await?;
+        if let Some(model) = ollama.model() {
+            chat.set_model(model);
+        }
+    }
+
+    /* Options precedence is CLI, recipe, persona then file */
+    let mut options = match &persona {
+        Some(persona) => {
+            let mut options = config.options.clone();
+            options.merge(&persona.options);
+            options
+        }
+        None => chat.options().clone(),
+    };
+    options.merge(&recipe.options);
+    options.merge(&args.options);
+    chat.set_options(options);
+
+    /* Command line system prompt overrides the persona one */
+    if let Some(system) = persona
+        .as_ref()
+        .and_then(|p| p.system.as_ref())
+        .filter(|_| args.system.is_none())
+    {
+        chat.set_system(system);
+    }
+
+    /* The persona tools are added to the ones of the chat */
+    if let Some(persona) = persona.as_ref().filter(|_| !args.no_enable_tools) {
+        register_tools(chat, false, persona)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(flatten)]
+    Model(ModelCommand),
+    /// Answer each new clipboard content and copy the answer back
+    Watch(WatchArgs),
+    /// Run a recipe from the configuration file
+    Run {
+        name: String,
+        /// Values of the {{1}}, {{2}}... slots of the recipe
+        args: Vec<String>,
+    },
+    /// Manage the recipes of the configuration file
+    Recipes {
+        #[command(subcommand)]
+        command: RecipesCommand,
+    },
+}
+
+/* Model management, run without the chat setup */
+#[derive(Subcommand, Debug)]
+enum ModelCommand {
+    /// Download a model from the registry
+    Pull { model: String },
+    /// Remove a model from the server
+    Rm { model: String },
+    /// Copy a model under a new name
+    Cp { source: String, destination: String },
+    /// Show the modelfile, parameters, template and license of a model
+    Show { model: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum RecipesCommand {
+    /// List the available recipes
+    List,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Prompt where ::CL:: is replaced by the clipboard content
+    #[arg(long, short)]
+    template: String,
+    /// Only answer contents starting with this prefix, which is removed
+    #[arg(long)]
+    trigger: Option<String>,
+    /// Milliseconds between clipboard reads
+    #[arg(long, default_value_t = 500)]
+    interval: u64,
+    /// Milliseconds the content must stay unchanged before it is answered
+    #[arg(long, default_value_t = 1000)]
+    debounce: u64,
 }
 
 #[derive(Parser, Debug)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Server URL, e.g. localhost:11434, [::1]:11434 or https://gw.example/ollama
+    #[arg(long, env = "OLLAMA_HOST", value_name = "URL")]
+    host: Option<String>,
+    /// Server API, openai for llama.cpp server, vLLM, LM Studio...
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
     /// Model to be used
-    #[arg(short, long)]
+    #[arg(short, long, env = "CLIPPYRS_MODEL")]
     model: Option<String>,
-    /// Force markdown output
+    /// Force markdown output, same as --render pager
     #[arg(short, long, default_value_t = false)]
     force_md: bool,
+    /// How answers are displayed
+    #[arg(long, value_enum)]
+    render: Option<Render>,
     /// List available models
     #[clap(long, short, action)]
     list_models: bool,
 
+    /// Output format of listings
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    /// Sort key of the model list
+    #[clap(long, value_enum, default_value_t = ModelSort::Name)]
+    sort: ModelSort,
+
+    /// Only list models whose name or family contains this text
+    #[clap(long, value_name = "TEXT")]
+    filter: Option<String>,
+
     /// Enable tools in queries (URL, calculator)
-    #[clap(long, short, action)]
-    enable_tools: bool,
+    #[clap(long, short, env = "CLIPPYRS_ENABLE_TOOLS", value_name = "BOOL", num_args = 0..=1,
+        require_equals = true, default_missing_value = "true", overrides_with = "no_enable_tools")]
+    enable_tools: Option<bool>,
+    /// Disable all tools, including the ones listed in the configuration file
+    #[clap(long, overrides_with = "enable_tools")]
+    no_enable_tools: bool,
 
     /// Store response to clipboard
+    #[clap(long, short, env = "CLIPPYRS_STORE_IN_CLIPBOARD", value_name = "BOOL", num_args = 0..=1,
+        require_equals = true, default_missing_value = "true", overrides_with = "no_store_in_clipboard")]
+    store_in_clipboard: Option<bool>,
+    /// Do not store response to clipboard, even if enabled elsewhere
+    #[clap(long, overrides_with = "store_in_clipboard")]
+    no_store_in_clipboard: bool,
+
+    /// Clipboard to use, detected from the session by default
+    #[clap(long, value_enum, value_name = "BACKEND")]
+    clipboard_backend: Option<ClipboardBackend>,
+
+    /// Largest OSC 52 clipboard payload, longer answers are truncated [default: 100000]
+    #[clap(long, value_name = "BYTES")]
+    osc52_max_bytes: Option<usize>,
+
+    /// Load and save the conversation in a named session
+    #[clap(long, value_name = "NAME")]
+    session: Option<String>,
+
+    /// List saved sessions
+    #[clap(long, action)]
+    list_sessions: bool,
+
+    /// Delete a saved session
+    #[clap(long, value_name = "NAME")]
+    delete_session: Option<String>,
+
+    /// Print a saved session as markdown
+    #[clap(long, value_name = "NAME")]
+    export_session: Option<String>,
+
+    /// System prompt for the conversation
+    #[clap(long)]
+    system: Option<String>,
+
+    /// Use a persona from the configuration file
+    #[clap(long, short, value_name = "NAME")]
+    persona: Option<String>,
+
+    /// Configuration file to use instead of ~/.config/clippyrs/config.toml
+    #[clap(long, short, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Request a JSON response
+    #[clap(long, action)]
+    json: bool,
+
+    /// Request a JSON response matching the schema in file
+    #[clap(long, value_name = "FILE")]
+    schema: Option<PathBuf>,
+
+    /// Number of retries when the JSON response is invalid
+    #[clap(long, default_value_t = 2)]
+    retries: u32,
+
+    /// Attach the content of a file to the prompt, may be repeated
+    #[clap(long, value_name = "PATH")]
+    file: Vec<PathBuf>,
+
+    /// Run ::CMD:: prompt variables without confirmation
     #[clap(long, short, action)]
-    store_in_clipboard: bool,
+    yes: bool,
+
+    /// Print token usage and timings after each answer
+    #[clap(long, action)]
+    stats: bool,
 
     /// Optionnal Prompt
     #[clap(last = true, allow_hyphen_values = true)]
     prompt: Option<Vec<String>>,
+
+    #[command(flatten, next_help_heading = "Generation options")]
+    options: Options,
+}
+
+fn register_tools(chat: &mut Chat, all: bool, persona: &Persona) -> Result<()> {
+    if all {
+        Tool::register_defaults(chat);
+    }
+
+    for name in persona.tools.iter() {
+        let tool = Tool::by_name(name).ok_or(anyhow!("No such tool '{}'", name))?;
+        chat.add_tool(tool);
+    }
+
+    Ok(())
 }
 
-async fn interactive(ollama: &Ollama, args: &Args, skin: &MadSkin) -> Result<()> {
+fn chat_new<B: Backend>(ollama: &B, args: &Args, persona: &Persona) -> Result<Chat> {
     let mut chat = ollama.context_new()?;
 
-    if args.enable_tools {
-        Tool::register_defaults(&mut chat);
+    /* Tools listed by name are kept unless all are disabled */
+    if !args.no_enable_tools {
+        register_tools(&mut chat, args.enable_tools == Some(true), persona)?;
+    }
+
+    chat.set_options(persona.options.clone());
+
+    if let Some(name) = &args.session {
+        SessionStore::new()?.restore(name, &mut chat)?;
+    }
+
+    if let Some(fmt) = format::from_args(args.json, args.schema.as_deref())? {
+        chat.set_format(fmt);
+    }
+
+    /* Command line system prompt overrides the persona one */
+    if let Some(system) = args.system.as_ref().or(persona.system.as_ref()) {
+        chat.set_system(system);
+    }
+
+    Ok(chat)
+}
+
+fn chat_save(session: Option<&String>, chat: &Chat) -> Result<()> {
+    if let Some(name) = session {
+        SessionStore::new()?.store(name, chat)?;
+    }
+
+    Ok(())
+}
+
+/// Send a prompt and run the requested tools, retrying on invalid JSON output
+async fn exchange<B: Backend>(
+    ollama: &B,
+    args: &Args,
+    prompt: &str,
+    chat: &mut Chat,
+) -> Result<()> {
+    let mut prompt = prompt.to_string();
+    let mut retries = 0;
+
+    loop {
+        let ret = ollama.chat(Some(prompt.as_str()), chat).await?;
+
+        /* Need to call tools */
+        if ret {
+            while ollama.chat(None, chat).await? {}
+        }
+
+        if chat.truncated() {
+            return Err(anyhow!("Generation interrupted"));
+        }
+
+        let fmt = match chat.format() {
+            Some(fmt) => fmt,
+            None => return Ok(()),
+        };
+
+        match format::check(fmt, &chat.response().unwrap_or_default()) {
+            Ok(_) => return Ok(()),
+            Err(e) if retries < args.retries => {
+                retries += 1;
+                warn!("Retrying ({}/{}) : {}", retries, args.retries, e);
+                prompt = format!(
+                    "Your answer is invalid ({}). Reply again with only the corrected JSON.",
+                    e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run a slash command, returns a prompt to send for `/retry`
+fn slash_command<B: Backend>(
+    cmd: SlashCommand,
+    args: &Args,
+    ollama: &mut B,
+    chat: &mut Chat,
+    session: &mut Option<String>,
+    persona: &Persona,
+    recipes: &BTreeMap<String, Recipe>,
+) -> Result<Option<String>> {
+    match cmd {
+        SlashCommand::Model(model) => {
+            ollama.set_model(&model)?;
+            if let Some(model) = ollama.model() {
+                chat.set_model(model);
+                println!("Using model '{}'", model);
+            }
+        }
+        SlashCommand::Clear => chat.clear(),
+        SlashCommand::Undo => {
+            if chat.undo().is_none() {
+                return Err(anyhow!("Nothing to undo"));
+            }
+            chat_save(session.as_ref(), chat)?;
+        }
+        SlashCommand::Retry => {
+            return chat.undo().map(Some).ok_or(anyhow!("Nothing to retry"));
+        }
+        SlashCommand::Save(name) => {
+            let name = name
+                .or(session.clone())
+                .ok_or(anyhow!("/save needs a session name"))?;
+            SessionStore::new()?.store(&name, chat)?;
+            println!("Saved session '{}'", name);
+            /* Following exchanges go to this session */
+            *session = Some(name);
+        }
+        SlashCommand::Load(name) => {
+            let store = SessionStore::new()?;
+            if store.load(&name)?.is_none() {
+                return Err(anyhow!("No such session '{}'", name));
+            }
+            store.restore(&name, chat)?;
+            println!("Loaded session '{}'", name);
+            *session = Some(name);
+        }
+        SlashCommand::System(prompt) => chat.set_system(&prompt),
+        SlashCommand::Tools(true) => register_tools(chat, true, persona)?,
+        SlashCommand::Tools(false) => chat.clear_tools(),
+        SlashCommand::Copy => {
+            let resp = chat.response().ok_or(anyhow!("No answer to copy"))?;
+            store_in_clipboard(args, &resp)?;
+        }
+        SlashCommand::Stats => {
+            let stats = chat.stats();
+            print_stats(&format!("{} requests", stats.requests), &stats);
+        }
+        SlashCommand::Help => {
+            println!("{}", SlashCommand::help());
+            for (name, recipe) in recipes {
+                let usage = (1..=recipe.arity())
+                    .map(|n| format!("<{}>", n))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                println!(
+                    "{:<8} {:<8} {}",
+                    format!("/{}", name),
+                    usage,
+                    recipe.description.as_deref().unwrap_or("Recipe")
+                );
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+async fn interactive<B: Backend>(
+    ollama: &mut B,
+    args: &Args,
+    persona: &Persona,
+    config: &Config,
+    skin: &MadSkin,
+) -> Result<()> {
+    let recipes = config.recipes();
+    let mut chat = chat_new(ollama, args, persona)?;
+    let mut session = args.session.clone();
+
+    let mut editor = LineEditor::new()?;
+    for (name, _, _) in COMMANDS.iter() {
+        let values = match *name {
+            "/model" => ollama.model_names(),
+            "/load" | "/save" => SessionStore::new()?.list()?,
+            "/tools" => vec!["on".to_string(), "off".to_string()],
+            _ => vec![],
+        };
+        editor.add_completion(name, values);
+    }
+    for name in recipes.keys() {
+        editor.add_completion(&format!("/{}", name), vec![]);
     }
 
-    user_prompt();
+    /* Files are attached to the first prompt */
+    let mut files = args.file.as_slice();
+
+    loop {
+        println!();
+
+        let line = match editor.read("User: ")? {
+            Some(line) => line,
+            None => break,
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
 
-    for line in std::io::stdin().lines() {
-        let mut line = prompt_unfold_vars(line.unwrap())?;
+        let mut recipe = None;
+
+        let prompt = if let Some(call) = recipe_call(&line, recipes) {
+            call.and_then(|(r, prompt)| {
+                recipe = Some(r);
+                prompt_unfold_vars(prompt, args)
+            })
+            .map(Some)
+        } else if let Some(cmd) = SlashCommand::parse(&line) {
+            cmd.and_then(|cmd| {
+                slash_command(cmd, args, ollama, &mut chat, &mut session, persona, recipes)
+            })
+        } else {
+            prompt_unfold_vars(line, args)
+                .and_then(|line| prompt_attach_input(line, None, files))
+                .map(Some)
+        };
+
+        let mut line = match prompt {
+            Ok(Some(line)) => line,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("{:#}", e);
+                continue;
+            }
+        };
+        files = &[];
+
+        /* A recipe sets its model, persona and options for its turn only */
+        let saved = recipe.map(|_| TurnSettings::save(&chat));
+        if let Some(r) = recipe {
+            if let Err(e) = recipe_apply(ollama, &mut chat, args, config, r).await {
+                error!("{:#}", e);
+                if let Some(saved) = saved {
+                    saved.restore(ollama, &mut chat)?;
+                }
+                continue;
+            }
+        }
 
         let domd = if line.starts_with('!') {
             line = line[1..].to_string();
@@ -137,59 +749,83 @@ async fn interactive(ollama: &Ollama, args: &Args, skin: &MadSkin) -> Result<()>
             false
         };
 
-        assistant_prompt();
+        assistant_prompt(ollama);
 
-        let ret = ollama.chat(Some(line.as_str()), &mut chat).await?;
+        let before = chat.stats();
 
-        /* Need to call tools */
-        if ret {
-            while ollama.chat(None, &mut chat).await? {}
+        if let Err(e) = exchange(ollama, args, line.as_str(), &mut chat).await {
+            error!("{}", e);
         }
 
+        if args.stats {
+            print_stats("answer", &chat.stats().since(&before));
+        }
+
+        if let Some(saved) = saved {
+            saved.restore(ollama, &mut chat)?;
+        }
+
+        chat_save(session.as_ref(), &chat)?;
+
         if let Some(resp) = chat.response() {
-            if domd || args.force_md {
+            if chat.format().is_some() {
+                println!("{}", resp);
+            }
+
+            if domd || args.force_md || recipe.is_some_and(|r| r.force_md) {
                 let _ = view_resp(skin.clone(), resp.clone());
             }
 
-            if args.store_in_clipboard {
-                store_in_clipboard(resp);
+            if args.store_in_clipboard == Some(true) || recipe.is_some_and(|r| r.store_in_clipboard)
+            {
+                if let Err(e) = store_in_clipboard(args, &resp) {
+                    error!("{:#}", e);
+                }
             }
         }
+    }
 
-        user_prompt()
+    if args.stats {
+        let stats = chat.stats();
+        print_stats(&format!("{} requests", stats.requests), &stats);
     }
 
     Ok(())
 }
 
-async fn single(
-    ollama: &Ollama,
+async fn single<B: Backend>(
+    ollama: &B,
     prompt: String,
+    stdin: Option<String>,
     args: &Args,
+    persona: &Persona,
     skin: &MadSkin,
 ) -> Result<Option<String>> {
-    let mut chat = ollama.context_new()?;
+    let mut chat = chat_new(ollama, args, persona)?;
 
-    if args.enable_tools {
-        Tool::register_defaults(&mut chat);
-    }
+    let prompt = prompt_unfold_vars(prompt, args)?;
+    let prompt = prompt_attach_input(prompt, stdin, &args.file)?;
 
-    let prompt = prompt_unfold_vars(prompt)?;
+    let ret = exchange(ollama, args, prompt.as_str(), &mut chat).await;
 
-    let ret = ollama.chat(Some(prompt.as_str()), &mut chat).await?;
-
-    /* Need to call tools */
-    if ret {
-        while ollama.chat(None, &mut chat).await? {}
+    if args.stats {
+        print_stats("answer", &chat.stats());
     }
 
+    chat_save(args.session.as_ref(), &chat)?;
+    ret?;
+
     if let Some(response) = chat.response() {
+        if chat.format().is_some() {
+            println!("{}", response);
+        }
+
         if args.force_md {
             let _ = view_resp(skin.clone(), response.clone());
         }
 
-        if args.store_in_clipboard {
-            store_in_clipboard(response);
+        if args.store_in_clipboard == Some(true) {
+            store_in_clipboard(args, &response)?;
         }
     }
 
@@ -197,35 +833,380 @@ async fn single(
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{} {:#}", "Error:".bold().red(), e);
+
+        /* API failures have their own exit codes */
+        let code = e.downcast_ref::<ApiError>().map_or(1, ApiError::exit_code);
+        process::exit(code);
+    }
+}
+
+/// Pull a model printing a progress bar on stderr
+async fn pull_model<B: Backend>(ollama: &B, model: &str) -> Result<()> {
+    let mut last_status = String::new();
+
+    ollama
+        .pull(model, |st| {
+            if st.status != last_status && !last_status.is_empty() {
+                eprintln!();
+            }
+            last_status = st.status.clone();
+
+            match (st.total, st.completed) {
+                (Some(total), Some(done)) if total > 0 => {
+                    let width = 30;
+                    let done = done.min(total);
+                    let filled = (done * width / total) as usize;
+                    eprint!(
+                        "\r{} [{}{}] {:>3}% {}/{}",
+                        st.status,
+                        "#".repeat(filled),
+                        " ".repeat(width as usize - filled),
+                        done * 100 / total,
+                        human_size(done),
+                        human_size(total)
+                    );
+                }
+                _ => eprint!("\r{}", st.status),
+            }
+            let _ = io::stderr().flush();
+        })
+        .await?;
+
+    eprintln!();
+
+    Ok(())
+}
+
+/// Select a model, offering to pull it when missing
+async fn select_model<B: Backend>(ollama: &mut B, model: &str) -> Result<()> {
+    let err = match ollama.set_model(model) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+
+    let missing = matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::ModelNotFound(_))
+    );
+
+    if !missing
+        || !ollama.manages_models()
+        || !io::stdin().is_terminal()
+        || !confirm(&format!("Model '{}' is not available, pull it ?", model))?
+    {
+        return Err(err);
+    }
+
+    pull_model(ollama, model).await?;
+    ollama.refresh_models().await?;
+    ollama.set_model(model)
+}
+
+async fn run_command<B: Backend>(ollama: &B, cmd: &ModelCommand) -> Result<()> {
+    match cmd {
+        ModelCommand::Pull { model } => pull_model(ollama, model).await,
+        ModelCommand::Rm { model } => {
+            ollama.delete_model(model).await?;
+            println!("Deleted '{}'", model);
+            Ok(())
+        }
+        ModelCommand::Cp {
+            source,
+            destination,
+        } => {
+            ollama.copy_model(source, destination).await?;
+            println!("Copied '{}' to '{}'", source, destination);
+            Ok(())
+        }
+        ModelCommand::Show { model } => {
+            print!("{}", ollama.show_model(model).await?);
+            Ok(())
+        }
+    }
+}
+
+/// Answer clipboard contents with `template` until Ctrl-C
+async fn watch<B: Backend>(
+    ollama: &B,
+    args: &Args,
+    persona: &Persona,
+    skin: &MadSkin,
+    w: &WatchArgs,
+) -> Result<()> {
+    let interval = Duration::from_millis(w.interval);
+    let debounce = Duration::from_millis(w.debounce);
+
+    /* The content is given as piped input so that it is never unfolded */
+    let template = w.template.replace("::CL::", "::STDIN::");
+
+    let clipboard = Clipboard::new(clipboard_backend(args));
+    if !clipboard.readable() {
+        return Err(anyhow!(
+            "Cannot watch a write only clipboard, use --clipboard-backend to read another one"
+        ));
+    }
+
+    /* What is already in the clipboard is not answered */
+    let mut last = clipboard.get().unwrap_or_default();
+    let mut changed: Option<Instant> = None;
+    /* Our own answers must not be answered again */
+    let mut answered: Option<String> = None;
+
+    eprintln!("Watching the clipboard, Ctrl-C to stop");
+    let _handled = interrupt::Handled::new();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        let content = match clipboard.get() {
+            Ok(content) => content,
+            Err(e) => {
+                error!("{:#}", e);
+                continue;
+            }
+        };
+
+        if content != last {
+            last = content;
+            changed = Some(Instant::now());
+            continue;
+        }
+
+        /* Wait for the content to settle */
+        match changed {
+            Some(at) if at.elapsed() >= debounce => changed = None,
+            _ => continue,
+        }
+
+        if answered.as_deref().map(str::trim) == Some(content.trim()) {
+            continue;
+        }
+
+        let input = match w.trigger.as_deref() {
+            Some(prefix) => match content.strip_prefix(prefix) {
+                Some(input) => input.trim_start(),
+                None => continue,
+            },
+            None => content.as_str(),
+        };
+
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        assistant_prompt(ollama);
+
+        match single(
+            ollama,
+            template.clone(),
+            Some(input.to_string()),
+            args,
+            persona,
+            skin,
+        )
+        .await
+        {
+            Ok(Some(response)) => {
+                /* Already stored by single() with --store-in-clipboard */
+                if args.store_in_clipboard != Some(true) {
+                    if let Err(e) = store_in_clipboard(args, &response) {
+                        error!("{:#}", e);
+                    }
+                }
+                last = clipboard.get().unwrap_or(response.clone());
+                answered = Some(response);
+            }
+            Ok(None) => {}
+            Err(e) => error!("{:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn run() -> Result<()> {
+    let mut args = Args::parse();
 
     env_logger::init();
+    interrupt::exit_when_unhandled();
 
-    let mut skin = MadSkin::default();
-    skin.table.align = Alignment::Center;
-    skin.set_headers_fg(AnsiValue(178));
-    skin.scrollbar.thumb.set_fg(AnsiValue(178));
-    skin.code_block.align = Alignment::Center;
+    let config = Config::load(args.config.as_deref())?;
 
-    let mut ollama = Ollama::default().await?;
+    args.clipboard_backend = args.clipboard_backend.or(config.clipboard_backend);
+    args.osc52_max_bytes = args.osc52_max_bytes.or(config.osc52_max_bytes);
 
-    if args.list_models {
-        ollama.print_models();
+    let skin = make_skin(&config.skin);
+
+    if let Some(Command::Recipes {
+        command: RecipesCommand::List,
+    }) = &args.command
+    {
+        print_recipes(&config);
         return Ok(());
     }
 
-    if let Some(model) = &args.model {
-        ollama.set_model(model.as_str())?;
+    /* A recipe is a prompt with its own defaults, the CLI wins */
+    let run = match &args.command {
+        Some(Command::Run { name, args: values }) => {
+            let recipe = config.recipe(name)?;
+            let prompt = recipe.fill(values)?;
+            Some((recipe, prompt))
+        }
+        _ => None,
+    };
+
+    if let Some((recipe, prompt)) = run {
+        args.prompt = Some(vec![prompt]);
+        args.model = args.model.or(recipe.model);
+        args.persona = args.persona.or(recipe.persona);
+        args.store_in_clipboard = args
+            .store_in_clipboard
+            .or(recipe.store_in_clipboard.then_some(true));
+        args.force_md |= recipe.force_md;
+        let mut options = recipe.options;
+        options.merge(&args.options);
+        args.options = options;
+        args.command = None;
     }
 
-    if let Some(prompt) = &args.prompt {
-        let pr = prompt.join(" ");
-        single(&ollama, pr, &args, &skin).await?;
+    /* Flags precedence is --no-flag, --flag or env, recipe then file */
+    args.enable_tools = Some(resolve_flag(
+        args.enable_tools,
+        args.no_enable_tools,
+        config.enable_tools,
+    ));
+    args.store_in_clipboard = Some(resolve_flag(
+        args.store_in_clipboard,
+        args.no_store_in_clipboard,
+        config.store_in_clipboard,
+    ));
+
+    /* --force-md is a shortcut for --render pager, the CLI wins */
+    let render = args
+        .render
+        .or(args.force_md.then_some(Render::Pager))
+        .or(config.render)
+        .or(config.force_md.then_some(Render::Pager))
+        .unwrap_or(Render::Raw);
+    args.force_md = render == Render::Pager;
+    args.render = Some(render);
+
+    if args.list_sessions {
+        for name in SessionStore::new()?.list()? {
+            println!("- {}", name);
+        }
+        return Ok(());
+    }
+
+    if let Some(name) = &args.delete_session {
+        SessionStore::new()?.delete(name)?;
+        return Ok(());
+    }
+
+    if let Some(name) = &args.export_session {
+        print!("{}", SessionStore::new()?.export(name)?);
+        return Ok(());
+    }
+
+    /* Host precedence is flag, env then file */
+    let host = args.host.clone().or(config.host.clone());
+    let client = config.http.client()?;
+
+    match args
+        .backend
+        .or(config.backend)
+        .unwrap_or(BackendKind::Ollama)
+    {
+        BackendKind::Ollama => {
+            let ollama = Ollama::new(host.as_deref(), client).await?;
+            run_backend(ollama, &args, &config, &skin).await
+        }
+        BackendKind::Openai => {
+            let openai = OpenAi::new(host.as_deref(), client).await?;
+            run_backend(openai, &args, &config, &skin).await
+        }
+    }
+}
+
+async fn run_backend<B: Backend>(
+    mut ollama: B,
+    args: &Args,
+    config: &Config,
+    skin: &MadSkin,
+) -> Result<()> {
+    /* Recipes are run before connecting, watching needs the chat setup
+    below */
+    if let Some(Command::Model(cmd)) = &args.command {
+        return run_command(&ollama, cmd).await;
+    }
+
+    if args.list_models {
+        return ollama.print_models(args.output, args.sort, args.filter.as_deref());
+    }
+
+    let mut persona = if let Some(name) = &args.persona {
+        config.persona(name)?
+    } else {
+        Persona::default()
+    };
+
+    if persona.tools.is_empty() {
+        persona.tools = config.tools.clone();
+    }
+
+    /* Options precedence is CLI, persona then file */
+    let mut options = config.options.clone();
+    options.merge(&persona.options);
+    options.merge(&args.options);
+    persona.options = options;
+
+    /* Model precedence is CLI (or env), persona then file */
+    if let Some(model) = args
+        .model
+        .as_ref()
+        .or(persona.model.as_ref())
+        .or(config.model.as_ref())
+    {
+        select_model(&mut ollama, model).await?;
+    }
+
+    /* Only print validated JSON responses */
+    if args.json || args.schema.is_some() {
+        ollama.set_echo(Echo::Off);
+    } else if args.render == Some(Render::Inline) && io::stdout().is_terminal() {
+        ollama.set_echo(Echo::Markdown(Box::new(skin.clone())));
+    }
+
+    if let Some(Command::Watch(w)) = &args.command {
+        return watch(&ollama, args, &persona, skin, w).await;
+    }
+
+    /* Piped input means single-shot mode */
+    let stdin = if io::stdin().is_terminal() {
+        None
+    } else {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        Some(input).filter(|i| !i.trim().is_empty())
+    };
+
+    if args.prompt.is_some() || stdin.is_some() {
+        let pr = args
+            .prompt
+            .as_ref()
+            .map(|p| p.join(" "))
+            .unwrap_or_default();
+        single(&ollama, pr, stdin, args, &persona, skin).await?;
         return Ok(());
     }
 
-    interactive(&ollama, &args, &skin).await?;
+    interactive(&mut ollama, args, &persona, config, skin).await?;
 
     Ok(())
 }
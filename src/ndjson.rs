@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use log::error;
+use serde::de::DeserializeOwned;
+
+use crate::error::{ApiError, ErrorBody};
+
+/// Incremental line splitter for newline delimited JSON streams
+///
+/// Chunks may end in the middle of a line or of a multibyte
+/// character, only complete lines are decoded.
+#[derive(Default)]
+pub struct LineBuffer {
+    buf: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Append a chunk and return the complete lines it terminates
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut ret: Vec<String> = Vec::new();
+
+        /* A newline byte is never part of a multibyte UTF-8 sequence */
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line =
+                String::from_utf8(line).map_err(|e| anyhow!("Invalid UTF-8 in stream : {}", e))?;
+            let line = line.trim();
+
+            if !line.is_empty() {
+                ret.push(line.to_string());
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Return the last line when the stream ends without a newline
+    pub fn finish(&mut self) -> Result<Option<String>> {
+        let rest = std::mem::take(&mut self.buf);
+        let rest = String::from_utf8(rest)
+            .map_err(|e| anyhow!("Truncated UTF-8 at end of stream : {}", e))?;
+        let rest = rest.trim();
+
+        Ok(if rest.is_empty() {
+            None
+        } else {
+            Some(rest.to_string())
+        })
+    }
+}
+
+/// Decode a JSON line, turning error bodies into `StreamInterrupted`
+pub fn parse_line<T: DeserializeOwned>(
+    line: &str,
+    f: &mut impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    /* Errors may also be reported in the stream, they are checked first
+    as lenient response types would decode them as empty chunks */
+    if let Ok(body) = serde_json::from_str::<ErrorBody>(line) {
+        return Err(ApiError::StreamInterrupted(body.message()).into());
+    }
+
+    match serde_json::from_str::<T>(line) {
+        Ok(v) => f(v),
+        Err(e) => {
+            error!("Failed to parse response '{}' : {}", line, e);
+            Ok(())
+        }
+    }
+}
+
+/// Call `f` for each JSON object of a streamed response
+pub async fn for_each<T: DeserializeOwned>(
+    resp: reqwest::Response,
+    mut f: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    let mut lines = LineBuffer::default();
+    let mut stream = resp.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(ApiError::from_stream)?;
+        for line in lines.push(&chunk)? {
+            parse_line(&line, &mut f)?;
+        }
+    }
+
+    if let Some(line) = lines.finish()? {
+        parse_line(&line, &mut f)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_split_across_chunks() {
+        let mut lines = LineBuffer::default();
+
+        assert!(lines.push(b"{\"content\": \"Hel").unwrap().is_empty());
+        assert_eq!(
+            lines.push(b"lo\"}\n").unwrap(),
+            vec!["{\"content\": \"Hello\"}"]
+        );
+        assert_eq!(lines.finish().unwrap(), None);
+    }
+
+    #[test]
+    fn multibyte_char_split_across_chunks() {
+        let line = "{\"content\": \"héllo\"}\n".as_bytes();
+        let cut = line.iter().position(|b| *b == 0xc3).unwrap() + 1;
+        let mut lines = LineBuffer::default();
+
+        assert!(lines.push(&line[..cut]).unwrap().is_empty());
+        assert_eq!(
+            lines.push(&line[cut..]).unwrap(),
+            vec!["{\"content\": \"héllo\"}"]
+        );
+    }
+
+    #[test]
+    fn several_lines_in_one_chunk() {
+        let mut lines = LineBuffer::default();
+
+        assert_eq!(
+            lines.push(b"{\"a\": 1}\n\n{\"a\": 2}\r\n{\"a\":").unwrap(),
+            vec!["{\"a\": 1}", "{\"a\": 2}"]
+        );
+        assert_eq!(lines.push(b" 3}\n").unwrap(), vec!["{\"a\": 3}"]);
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        let mut lines = LineBuffer::default();
+
+        assert_eq!(
+            lines.push(b"{\"a\": 1}\n{\"done\": true}").unwrap(),
+            vec!["{\"a\": 1}"]
+        );
+        assert_eq!(
+            lines.finish().unwrap(),
+            Some("{\"done\": true}".to_string())
+        );
+        assert_eq!(lines.finish().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_char_at_end() {
+        let mut lines = LineBuffer::default();
+
+        lines.push(&"é".as_bytes()[..1]).unwrap();
+        assert!(lines.finish().is_err());
+    }
+}
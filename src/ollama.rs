@@ -1,13 +1,17 @@
-use anyhow::{anyhow, Context, Result};
-use futures::StreamExt;
-use log::{error, info};
+use anyhow::{anyhow, Result};
+use log::info;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
-use std::io::Write;
-use std::rc::Rc;
-use std::{env, io};
+use std::fmt;
 
 use url::Url;
+
+use crate::backend::{parse_host, Backend};
+use crate::error::ApiError;
+use crate::ndjson;
+use crate::render::{Echo, Printer};
+use crate::table::{human_size, print_table};
 use url_open::UrlOpen;
 
 /* Model Description */
@@ -46,7 +50,8 @@ use url_open::UrlOpen;
         "quantization_level": "Q4_0"
       }
 */
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct OllamaModelDetails {
     parent_model: String,
     format: String,
@@ -60,6 +65,7 @@ struct OllamaModelDetails {
 struct OllamaModel {
     pub name: String,
     model: Option<String>,
+    modified_at: Option<String>,
     size: usize,
     digest: String,
     details: OllamaModelDetails,
@@ -86,15 +92,58 @@ struct OllamaModels {
    ]
 */
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ToolCall {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ToolCallFunction {
     name: String,
-    parameters: HashMap<String, String>,
+    arguments: ToolArgs,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ToolCalls {
-    function: Vec<ToolCall>,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolCall {
+    /// Identifier given by OpenAI compatible servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    function: ToolCallFunction,
+}
+
+impl ToolCall {
+    pub fn new(id: Option<String>, name: &str, arguments: ToolArgs) -> ToolCall {
+        ToolCall {
+            id,
+            function: ToolCallFunction {
+                name: name.to_string(),
+                arguments,
+            },
+        }
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+
+    pub fn arguments(&self) -> &ToolArgs {
+        &self.function.arguments
+    }
+}
+
+/* Legacy tool call emitted as plain text by models
+without native tool support:
+   {"name": "math_calculator", "parameters": {"expression": "2+2"}}
+*/
+#[derive(Deserialize, Debug)]
+struct TextToolCall {
+    name: String,
+    parameters: ToolArgs,
+}
+
+impl From<TextToolCall> for ToolCall {
+    fn from(call: TextToolCall) -> ToolCall {
+        ToolCall::new(None, &call.name, call.parameters)
+    }
 }
 
 /** Message
@@ -115,11 +164,36 @@ struct ToolCalls {
   }
 */
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     role: String,
     content: String,
-    tool_calls: Option<ToolCalls>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    /// Call answered by a tool message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    /// Generation was interrupted by the user
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+impl Message {
+    pub fn role(&self) -> &str {
+        &self.role
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        self.tool_calls.as_deref().unwrap_or_default()
+    }
+
+    pub fn tool_call_id(&self) -> Option<&str> {
+        self.tool_call_id.as_deref()
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -127,10 +201,43 @@ struct ToolFunctionParam {
     #[serde(rename = "type")]
     __type: String,
     description: String,
-    #[serde(rename = "enum")]
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
     __enum: Option<Vec<String>>,
 }
 
+impl ToolFunctionParam {
+    fn check(&self, value: &Value) -> Result<()> {
+        let type_ok = match self.__type.as_str() {
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            "null" => value.is_null(),
+            /* Unknown types are not checked */
+            _ => true,
+        };
+
+        if !type_ok {
+            return Err(anyhow!("expected a {} got {}", self.__type, value));
+        }
+
+        if let Some(allowed) = &self.__enum {
+            let as_str = match value {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+
+            if !allowed.contains(&as_str) {
+                return Err(anyhow!("{} is not one of {:?}", value, allowed));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Debug)]
 struct ToolFunctionParameters {
     #[serde(rename = "type")]
@@ -146,25 +253,28 @@ struct ToolFunction {
     parameters: ToolFunctionParameters,
 }
 
+/// Tool arguments as sent by the model, keyed by parameter name
+pub type ToolArgs = HashMap<String, Value>;
+
 #[derive(Serialize)]
 pub struct Tool {
     #[serde(rename = "type")]
     __type: String,
     function: ToolFunction,
     #[serde(skip_serializing)]
-    closure: Box<dyn Fn(Vec<String>) -> String>,
+    closure: Box<dyn Fn(ToolArgs) -> String>,
 }
 
 impl Tool {
     /// An expression calculator
     pub fn calculator() -> Tool {
-        let f = Box::new(|args: Vec<String>| {
-            /* We need exactly two arguments */
-            if args.len() != 1 {
-                return "Operation failed as a single operand is needed".to_string();
-            }
+        let f = Box::new(|args: ToolArgs| {
+            let expr = match args.get("expression").and_then(Value::as_str) {
+                Some(expr) => expr,
+                None => return "Operation failed as an expression is needed".to_string(),
+            };
 
-            match meval::eval_str(args.first().unwrap()) {
+            match meval::eval_str(expr) {
                 Ok(resp) => format!("{}", resp),
                 Err(e) => e.to_string(),
             }
@@ -182,13 +292,13 @@ impl Tool {
     }
 
     pub fn url_open() -> Tool {
-        let f = Box::new(|args: Vec<String>| {
-            /* We need exactly two arguments */
-            if args.len() != 1 {
-                return "Operation failed as a single URL argument is needed".to_string();
-            }
+        let f = Box::new(|args: ToolArgs| {
+            let url = match args.get("url").and_then(Value::as_str) {
+                Some(url) => url,
+                None => return "Operation failed as an URL argument is needed".to_string(),
+            };
 
-            if let Ok(url) = Url::parse(args.first().unwrap()) {
+            if let Ok(url) = Url::parse(url) {
                 url.open();
                 "URL successfully opened".to_string()
             } else {
@@ -208,9 +318,9 @@ impl Tool {
         ret
     }
 
-    pub fn new(name: &str, description: &str, f: Box<dyn Fn(Vec<String>) -> String>) -> Tool {
+    pub fn new(name: &str, description: &str, f: Box<dyn Fn(ToolArgs) -> String>) -> Tool {
         Tool {
-            __type: "object".to_string(),
+            __type: "function".to_string(),
             closure: f,
             function: ToolFunction {
                 name: name.to_string(),
@@ -241,25 +351,32 @@ impl Tool {
         );
     }
 
-    fn extract_args(&self, parameters: HashMap<String, String>) -> Result<Vec<String>> {
-        let mut ret: Vec<String> = Vec::new();
+    fn extract_args(&self, arguments: ToolArgs) -> Result<ToolArgs> {
+        let params = &self.function.parameters;
 
         /* Check for required args */
-        for arg in self.function.parameters.properties.keys() {
-            if let Some(prop) = parameters.get(arg) {
-                ret.push(prop.clone());
-            } else {
+        for arg in params.required.iter() {
+            if !arguments.contains_key(arg) {
                 return Err(anyhow!(
-                    "No such argument '{}' to function '{}'",
+                    "Missing required argument '{}' to function '{}'",
                     arg,
                     self.function.name
                 ));
             }
         }
 
-        /* Check for extra arg */
-        for arg in parameters.keys() {
-            if !self.function.parameters.properties.contains_key(arg) {
+        /* Check for extra args and types */
+        for (arg, value) in arguments.iter() {
+            if let Some(param) = params.properties.get(arg) {
+                param.check(value).map_err(|e| {
+                    anyhow!(
+                        "Bad argument '{}' to function '{}' : {}",
+                        arg,
+                        self.function.name,
+                        e
+                    )
+                })?;
+            } else {
                 return Err(anyhow!(
                     "Function '{}' does not take a '{}' argument",
                     self.function.name,
@@ -268,7 +385,7 @@ impl Tool {
             }
         }
 
-        Ok(ret)
+        Ok(arguments)
     }
 
     pub fn set_required(&mut self, arg: &str) -> Result<()> {
@@ -286,71 +403,335 @@ impl Tool {
         ))
     }
 
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+
+    /// Get a builtin tool from its function name
+    pub fn by_name(name: &str) -> Option<Tool> {
+        match name {
+            "math_calculator" => Some(Tool::calculator()),
+            "open_url" => Some(Tool::url_open()),
+            _ => None,
+        }
+    }
+
     pub fn register_defaults(chat: &mut Chat) {
         chat.add_tool(Tool::calculator());
         chat.add_tool(Tool::url_open());
     }
 }
 
+/* Generation options of the /api/chat request */
+#[derive(Serialize, Deserialize, Debug, Default, Clone, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    /// Sampling temperature (0 for deterministic output)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling probability
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Size of the context window in tokens
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    /// Random seed for reproducible output
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Maximum number of tokens to generate (-1 for infinite)
+    #[arg(long, allow_negative_numbers = true)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    /// Stop sequence, may be repeated
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl Options {
+    fn is_empty(&self) -> bool {
+        self.temperature.is_none()
+            && self.top_p.is_none()
+            && self.num_ctx.is_none()
+            && self.seed.is_none()
+            && self.num_predict.is_none()
+            && self.stop.is_empty()
+    }
+
+    /// Override current options with the ones set in `other`
+    pub fn merge(&mut self, other: &Options) {
+        self.temperature = other.temperature.or(self.temperature);
+        self.top_p = other.top_p.or(self.top_p);
+        self.num_ctx = other.num_ctx.or(self.num_ctx);
+        self.seed = other.seed.or(self.seed);
+        self.num_predict = other.num_predict.or(self.num_predict);
+        if !other.stop.is_empty() {
+            self.stop = other.stop.clone();
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct Chat {
     model: String,
     messages: Vec<Message>,
     tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Options::is_empty")]
+    options: Options,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    #[serde(skip)]
+    stats: Stats,
 }
 
 impl Chat {
-    fn new(model: &str) -> Chat {
+    pub fn new(model: &str) -> Chat {
         Chat {
             model: model.to_string(),
             messages: vec![],
             tools: vec![],
+            options: Options::default(),
+            format: None,
+            stats: Stats::default(),
         }
     }
 
-    fn init(model: &str, prompt: &str) -> Chat {
-        Chat {
-            model: model.to_string(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-                tool_calls: None,
-            }],
-            tools: vec![],
+    pub fn add_tool(&mut self, tool: Tool) {
+        /* Do not register the same tool twice */
+        if self.get_tool(&tool.function.name).is_none() {
+            self.tools.push(tool);
         }
     }
 
-    pub fn add_tool(&mut self, tool: Tool) {
-        self.tools.push(tool);
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
     }
 
-    pub fn get_tool(&self, name: &str) -> Option<Rc<&Tool>> {
-        for t in self.tools.iter() {
-            if t.function.name == name {
-                return Some(Rc::new(t));
-            }
+    pub fn clear_tools(&mut self) {
+        self.tools.clear();
+    }
+
+    /// Cumulated stats of all the requests of this chat
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Last answer was interrupted by the user
+    pub fn truncated(&self) -> bool {
+        self.messages.last().is_some_and(|m| m.truncated)
+    }
+
+    /// Drop all messages but the system prompt
+    pub fn clear(&mut self) {
+        self.messages.retain(|m| m.role == "system");
+    }
+
+    /// Drop the last exchange and return its user prompt
+    pub fn undo(&mut self) -> Option<String> {
+        let last = self.messages.iter().rposition(|m| m.role == "user")?;
+        let prompt = self.messages[last].content.clone();
+        self.messages.truncate(last);
+        Some(prompt)
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    /// Request structured output, "json" or a JSON schema
+    pub fn set_format(&mut self, format: Value) {
+        self.format = Some(format);
+    }
+
+    pub fn format(&self) -> Option<&Value> {
+        self.format.as_ref()
+    }
+
+    /// Set the system prompt, replacing any previous one
+    pub fn set_system(&mut self, prompt: &str) {
+        let msg = Message {
+            role: "system".to_string(),
+            content: prompt.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            truncated: false,
+        };
+
+        match self.messages.first_mut() {
+            Some(first) if first.role == "system" => *first = msg,
+            _ => self.messages.insert(0, msg),
         }
+    }
 
-        None
+    pub fn system(&self) -> Option<&str> {
+        self.messages
+            .first()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+    }
+
+    pub fn clear_system(&mut self) {
+        if self.system().is_some() {
+            self.messages.remove(0);
+        }
+    }
+
+    pub fn get_tool(&self, name: &str) -> Option<&Tool> {
+        self.tools.iter().find(|t| t.function.name == name)
     }
 
-    fn add_prompt(&mut self, prompt: &str) {
+    fn call_tool(&self, call: &ToolCall) -> Message {
+        let content = match self.get_tool(&call.function.name) {
+            Some(tool) => match tool.extract_args(call.function.arguments.clone()) {
+                Ok(args) => (tool.closure)(args),
+                Err(e) => format!("Error calling {} : {}", call.function.name, e),
+            },
+            None => format!("No such tool '{}'", call.function.name),
+        };
+
+        info!("Tool '{}' returned '{}'", call.function.name, content);
+
+        Message {
+            role: "tool".to_string(),
+            content,
+            tool_calls: None,
+            tool_call_id: call.id.clone(),
+            truncated: false,
+        }
+    }
+
+    pub fn add_prompt(&mut self, prompt: &str) {
         self.messages.push(Message {
             role: "user".to_string(),
             content: prompt.to_string(),
             tool_calls: None,
+            tool_call_id: None,
+            truncated: false,
         })
     }
 
+    /// Record an answer and run the tools it calls, true if any was
+    pub fn end_turn(&mut self, reply: Reply, truncated: bool) -> bool {
+        let Reply {
+            content,
+            mut tool_calls,
+            stats,
+            ..
+        } = reply;
+
+        if truncated {
+            info!("Generation interrupted");
+            self.messages.push(Message {
+                role: "assistant".to_string(),
+                content,
+                tool_calls: None,
+                tool_call_id: None,
+                truncated: true,
+            });
+            return false;
+        }
+
+        self.stats.add(&stats);
+
+        /* Fallback for models emitting the call as a JSON text */
+        if tool_calls.is_empty() {
+            if let Ok(call) = serde_json::from_str::<TextToolCall>(content.trim()) {
+                if self.get_tool(&call.name).is_some() {
+                    tool_calls.push(call.into());
+                }
+            }
+        }
+
+        self.messages.push(Message {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls.clone())
+            },
+            tool_call_id: None,
+            truncated: false,
+        });
+
+        for call in tool_calls.iter() {
+            let resp = self.call_tool(call);
+            self.messages.push(resp);
+        }
+
+        !tool_calls.is_empty()
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn messages(&self) -> &Vec<Message> {
+        &self.messages
+    }
+
+    pub fn set_messages(&mut self, messages: Vec<Message>) {
+        self.messages = messages;
+    }
+
     pub fn response(&self) -> Option<String> {
         self.messages
             .iter()
-            .filter(|v| v.role == "assistant")
-            .last()
+            .rfind(|v| v.role == "assistant")
             .map(|v| v.content.clone())
     }
 }
 
+/// Answer being streamed by a backend
+pub struct Reply {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    stats: Stats,
+    printer: Printer,
+}
+
+impl Reply {
+    /// The content is printed as it arrives according to `echo`
+    pub fn new(echo: Echo) -> Reply {
+        Reply {
+            content: String::new(),
+            tool_calls: vec![],
+            stats: Stats::default(),
+            printer: Printer::new(echo),
+        }
+    }
+
+    pub fn push_content(&mut self, text: &str) -> Result<()> {
+        self.content += text;
+        self.printer.push(text)
+    }
+
+    /// Print the end of the content
+    pub fn finish(&mut self) -> Result<()> {
+        self.printer.finish()
+    }
+
+    pub fn push_tool_call(&mut self, call: ToolCall) {
+        self.tool_calls.push(call);
+    }
+
+    pub fn set_stats(&mut self, stats: Stats) {
+        self.stats = stats;
+    }
+}
+
 /** Chat response
  * {
   "model": "registry.ollama.ai/library/llama3:latest",
@@ -371,224 +752,302 @@ impl Chat {
 
 #[derive(Deserialize, Debug)]
 struct ChatResponse {
-    model: String,
-    created_at: String,
     message: Message,
     done: bool,
     total_duration: Option<u64>,
     load_duration: Option<u64>,
     prompt_eval_count: Option<u64>,
-    prompt_eval_duration: Option<u64>,
     eval_count: Option<u64>,
     eval_duration: Option<u64>,
 }
 
-pub struct Ollama {
-    host: String,
-    port: i32,
-    models: Option<Vec<OllamaModel>>,
-    current_model: Option<String>,
+impl ChatResponse {
+    fn stats(&self) -> Stats {
+        Stats {
+            requests: 1,
+            prompt_tokens: self.prompt_eval_count.unwrap_or(0),
+            eval_tokens: self.eval_count.unwrap_or(0),
+            eval_duration: self.eval_duration.unwrap_or(0),
+            load_duration: self.load_duration.unwrap_or(0),
+            total_duration: self.total_duration.unwrap_or(0),
+        }
+    }
 }
 
-impl Ollama {
-    fn url(&self) -> String {
-        if self.port != 0 {
-            format!("{}:{}", self.host, self.port)
-        } else {
-            self.host.clone()
-        }
+/// Token usage and timings, durations are in nanoseconds
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub eval_tokens: u64,
+    pub eval_duration: u64,
+    pub load_duration: u64,
+    pub total_duration: u64,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.eval_tokens += other.eval_tokens;
+        self.eval_duration += other.eval_duration;
+        self.load_duration += other.load_duration;
+        self.total_duration += other.total_duration;
     }
 
-    fn endpoint(&self, ep: &str) -> String {
-        let ret = format!("{}/{}", self.url(), ep).replace("//", "/");
-        info!("Endpoint is {}", ret);
-        ret
+    /// Stats accumulated since `before` was taken
+    pub fn since(&self, before: &Stats) -> Stats {
+        Stats {
+            requests: self.requests - before.requests,
+            prompt_tokens: self.prompt_tokens - before.prompt_tokens,
+            eval_tokens: self.eval_tokens - before.eval_tokens,
+            eval_duration: self.eval_duration - before.eval_duration,
+            load_duration: self.load_duration - before.load_duration,
+            total_duration: self.total_duration - before.total_duration,
+        }
     }
 
-    async fn loaded_models(&self) -> Result<OllamaModels> {
-        let resp = reqwest::get(self.endpoint("api/ps")).await?;
-        let models: OllamaModels = resp.json().await?;
+    pub fn tokens_per_second(&self) -> f64 {
+        if self.eval_duration == 0 {
+            return 0.0;
+        }
+        self.eval_tokens as f64 / (self.eval_duration as f64 / 1e9)
+    }
+}
 
-        Ok(models)
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} tokens in, {} tokens out, {:.1} tokens/s, load {:.2}s, total {:.2}s",
+            self.prompt_tokens,
+            self.eval_tokens,
+            self.tokens_per_second(),
+            self.load_duration as f64 / 1e9,
+            self.total_duration as f64 / 1e9
+        )
     }
+}
 
-    async fn list_models(&self) -> Result<OllamaModels> {
-        let resp = reqwest::get(self.endpoint("api/tags")).await?;
-        let models: OllamaModels = resp.json().await?;
+/* Pull progress
+{
+  "status": "pulling 2ae6f6dd7a3d",
+  "digest": "sha256:2ae6f6dd7a3dd734790bbbf58b8909a606e0e7e97e94b7604e0aa7ae4490e6d8",
+  "total": 5137025024,
+  "completed": 241970
+}
+*/
+#[derive(Deserialize, Debug)]
+pub struct PullStatus {
+    pub status: String,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+}
 
-        Ok(models)
-    }
+/* Model information from /api/show
+{
+  "modelfile": "# Modelfile generated by \"ollama show\" ...",
+  "parameters": "stop \"[INST]\"\nstop \"[/INST]\"",
+  "template": "[INST] {{ .System }} {{ .Prompt }} [/INST]",
+  "license": "...",
+  "details": { ... }
+}
+*/
+#[derive(Deserialize, Debug)]
+pub struct ModelInfo {
+    #[serde(default)]
+    modelfile: String,
+    #[serde(default)]
+    parameters: String,
+    #[serde(default)]
+    template: String,
+    #[serde(default)]
+    license: String,
+    details: Option<OllamaModelDetails>,
+}
 
-    pub fn print_models(&self) {
-        if let Some(models) = &self.models {
-            for m in models.iter() {
-                println!(
-                    "- {} {} {}",
-                    m.name,
-                    m.details.family,
-                    m.details.parameter_size.clone().unwrap_or("".to_string())
-                );
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(d) = &self.details {
+            writeln!(f, "# Details\n")?;
+            writeln!(f, "family        {}", d.family)?;
+            writeln!(f, "format        {}", d.format)?;
+            if let Some(size) = &d.parameter_size {
+                writeln!(f, "parameters    {}", size)?;
             }
+            if let Some(quant) = &d.quantization_level {
+                writeln!(f, "quantization  {}", quant)?;
+            }
+            writeln!(f)?;
         }
-    }
 
-    pub async fn default() -> Result<Ollama> {
-        let (host, port) = if let Ok(ollamahost) = env::var("OLLAMA_HOST") {
-            let addr_no_proto = ollamahost.replace("http://", "").replace("https://", "");
-
-            if addr_no_proto.contains(':') {
-                let sp: Vec<&str> = addr_no_proto.split(':').collect();
-
-                if let (Some(host), Some(port)) = (sp.first(), sp.get(1)) {
-                    println!("PORT : {}", port);
-                    let port = port.parse::<i32>()?;
-                    (host.to_string(), port)
-                } else {
-                    return Err(anyhow!("Failed to parse host and port from {}", ollamahost));
-                }
-            } else if ollamahost.starts_with("https://") || ollamahost.starts_with("http://") {
-                (ollamahost, 0)
-            } else if !ollamahost.starts_with("http") {
-                (format!("http://{}", ollamahost), 11434)
-            } else {
-                return Err(anyhow!("Failed to parse OLLAMA_HOST {}", ollamahost));
+        for (title, content) in [
+            ("Parameters", &self.parameters),
+            ("Template", &self.template),
+            ("Modelfile", &self.modelfile),
+            ("License", &self.license),
+        ] {
+            if !content.trim().is_empty() {
+                writeln!(f, "# {}\n\n{}\n", title, content.trim_end())?;
             }
-        } else {
-            ("http://localhost".to_string(), 11434)
-        };
+        }
 
-        Ollama::init(&host, port).await
+        Ok(())
     }
+}
 
-    pub fn set_model(&mut self, model: &str) -> Result<()> {
-        /* Make sure model exists */
-        let mut tmp_model = model.to_string();
-
-        if let Some(models) = &self.models {
-            let model_list: Vec<String> = models.iter().map(|v| v.name.clone()).collect();
+/// How `--list-models` sorts models
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ModelSort {
+    Name,
+    Family,
+    Size,
+    Modified,
+}
 
-            if !model_list.contains(&tmp_model) {
-                // Try to append :latest
-                tmp_model += ":latest";
-                if !model_list.contains(&tmp_model) {
-                    return Err(anyhow!(
-                        "Cannot load model '{}' available models are {:?}",
-                        model,
-                        model_list
-                    ));
-                }
-            }
-        }
+/// How listings are printed
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
 
-        self.current_model = Some(tmp_model);
+#[derive(Serialize, Debug)]
+struct ModelListing {
+    name: String,
+    family: String,
+    parameter_size: Option<String>,
+    quantization_level: Option<String>,
+    size: usize,
+    digest: String,
+    modified_at: Option<String>,
+    loaded: bool,
+    size_vram: Option<usize>,
+    expires_at: Option<String>,
+}
 
-        Ok(())
+/// Shorten an RFC 3339 date to local "YYYY-MM-DD HH:MM"
+fn short_date(date: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(date) {
+        Ok(d) => d
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        Err(_) => date.to_string(),
     }
+}
 
-    pub fn context_new(&self) -> Result<Chat> {
-        if let Some(model) = &self.current_model {
-            Ok(Chat::new(model.as_str()))
-        } else {
-            Err(anyhow!("No current model set"))
-        }
+pub struct Ollama {
+    base: Url,
+    client: reqwest::Client,
+    models: Option<Vec<OllamaModel>>,
+    loaded: Vec<OllamaModel>,
+    current_model: Option<String>,
+    echo: Echo,
+}
+
+impl Ollama {
+    async fn get(&self, ep: &str) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .get(self.endpoint(ep))
+            .send()
+            .await
+            .map_err(ApiError::from)?;
+
+        Ok(ApiError::check(resp).await?)
     }
 
-    pub async fn chat(&self, prompt: Option<&str>, context: &mut Chat) -> Result<bool> {
-        /* Add user request */
-        if let Some(prompt) = prompt {
-            context.add_prompt(prompt);
-        }
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let resp = req.send().await.map_err(ApiError::from)?;
 
-        let client = reqwest::Client::new();
+        Ok(ApiError::check(resp).await?)
+    }
 
-        let mut res = client
-            .post(self.endpoint("api/chat"))
-            .json(&context)
-            .send()
-            .await?
-            .bytes_stream()
-            .map(|x| x.unwrap());
-
-        //One line here
-        let mut assistant_resp = String::new();
-
-        while let Some(item) = res.next().await {
-            let s = std::str::from_utf8(&item)?.trim();
-
-            for line in s.split('\n') {
-                //println!("'{}'", line);
-                match serde_json::from_str::<ChatResponse>(line) {
-                    Ok(chat_resp) => {
-                        assistant_resp += chat_resp.message.content.as_str();
-                        print!("{}", chat_resp.message.content);
-                        io::stdout().flush()?;
-                    }
-                    Err(e) => {
-                        error!("Failed to parse response '{}' : {}", line, e);
-                    }
-                }
-            }
-        }
+    async fn loaded_models(&self) -> Result<OllamaModels> {
+        let resp = self.get("api/ps").await?;
+        let models: OllamaModels = resp.json().await.map_err(ApiError::from)?;
 
-        println!();
+        Ok(models)
+    }
 
-        /* Check if last command is a function call */
-        let call = match serde_json::from_str::<ToolCall>(assistant_resp.as_str()) {
-            Ok(call) => Some(call),
-            Err(_) => None,
-        };
+    async fn list_models(&self) -> Result<OllamaModels> {
+        let resp = self.get("api/tags").await?;
+        let models: OllamaModels = resp.json().await.map_err(ApiError::from)?;
 
-        context.messages.push(Message {
-            role: "assistant".to_string(),
-            content: assistant_resp,
-            tool_calls: None,
-        });
+        Ok(models)
+    }
 
-        if let Some(call) = call {
-            if let Some(tool) = context.get_tool(&call.name) {
-                match tool.extract_args(call.parameters) {
-                    Ok(args) => {
-                        let resp = (tool.closure)(args);
-                        context.messages.push(Message {
-                            role: "tool".to_string(),
-                            content: resp,
-                            tool_calls: None,
-                        });
-                    }
-                    Err(e) => {
-                        context.messages.push(Message {
-                            role: "tool".to_string(),
-                            content: format!("Error calling {} : {}", call.name, e),
-                            tool_calls: None,
-                        });
-                    }
+    /// Available models joined with their loaded state
+    fn model_listing(&self, sort: ModelSort, filter: Option<&str>) -> Vec<ModelListing> {
+        let mut ret: Vec<ModelListing> = self
+            .models
+            .iter()
+            .flatten()
+            .filter(|m| filter.is_none_or(|f| m.name.contains(f) || m.details.family.contains(f)))
+            .map(|m| {
+                let loaded = self.loaded.iter().find(|l| l.name == m.name);
+                ModelListing {
+                    name: m.name.clone(),
+                    family: m.details.family.clone(),
+                    parameter_size: m.details.parameter_size.clone(),
+                    quantization_level: m.details.quantization_level.clone(),
+                    size: m.size,
+                    digest: m.digest.clone(),
+                    modified_at: m.modified_at.clone(),
+                    loaded: loaded.is_some(),
+                    size_vram: loaded.and_then(|l| l.size_vram),
+                    expires_at: loaded.and_then(|l| l.expires_at.clone()),
                 }
+            })
+            .collect();
 
-                return Ok(true);
+        match sort {
+            ModelSort::Name => ret.sort_by(|a, b| a.name.cmp(&b.name)),
+            ModelSort::Family => {
+                ret.sort_by(|a, b| (&a.family, &a.name).cmp(&(&b.family, &b.name)))
             }
+            ModelSort::Size => ret.sort_by_key(|m| std::cmp::Reverse(m.size)),
+            /* Newest first, dates differ in precision and offset so they
+            are compared parsed, unparsable ones last */
+            ModelSort::Modified => ret.sort_by_cached_key(|m| {
+                std::cmp::Reverse(
+                    m.modified_at
+                        .as_deref()
+                        .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok()),
+                )
+            }),
         }
 
-        Ok(false)
+        ret
     }
 
-    pub async fn init(host: &str, port: i32) -> Result<Ollama> {
+    /// Connect with `client` to the given host, `OLLAMA_HOST` syntax, or localhost
+    pub async fn new(host: Option<&str>, client: reqwest::Client) -> Result<Ollama> {
+        let base = parse_host(host.unwrap_or("localhost"))?;
+        Ollama::init(base, client).await
+    }
+
+    pub async fn init(base: Url, client: reqwest::Client) -> Result<Ollama> {
         /* First check the server availaibility */
 
-        info!("Connecting to {} : {}", host, port);
+        info!("Connecting to {}", base);
 
         let mut ret = Ollama {
-            host: host.to_string(),
-            port,
+            base,
+            client,
             models: None,
+            loaded: vec![],
             current_model: None,
+            echo: Echo::Raw,
         };
 
         /* Here negotiate a model to use from current state
         it is also the opportunity to probe the API */
 
         ret.models = Some(ret.list_models().await?.models);
-        let current_model = ret.loaded_models().await?;
-        ret.current_model = if let Some(first) = current_model.models.first() {
+        ret.loaded = ret.loaded_models().await?.models;
+        ret.current_model = if let Some(first) = ret.loaded.first() {
             log::info!("Using loaded model '{}'", first.name);
             Some(first.name.clone())
         } else if let Some(avail_models) = &ret.models {
@@ -610,3 +1069,189 @@ impl Ollama {
         Ok(ret)
     }
 }
+
+impl Backend for Ollama {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn base(&self) -> &Url {
+        &self.base
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.current_model.as_deref()
+    }
+
+    fn echo(&self) -> Echo {
+        self.echo.clone()
+    }
+
+    fn set_echo(&mut self, echo: Echo) {
+        self.echo = echo;
+    }
+
+    fn manages_models(&self) -> bool {
+        true
+    }
+
+    async fn pull(&self, model: &str, mut progress: impl FnMut(&PullStatus)) -> Result<()> {
+        let req = self
+            .client
+            .post(self.endpoint("api/pull"))
+            .json(&serde_json::json!({ "model": model, "stream": true }));
+        let resp = self.send(req).await?;
+
+        ndjson::for_each(resp, |status: PullStatus| {
+            progress(&status);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_model(&self, model: &str) -> Result<()> {
+        let req = self
+            .client
+            .delete(self.endpoint("api/delete"))
+            .json(&serde_json::json!({ "model": model }));
+        self.send(req).await?;
+
+        Ok(())
+    }
+
+    async fn copy_model(&self, source: &str, destination: &str) -> Result<()> {
+        let req = self
+            .client
+            .post(self.endpoint("api/copy"))
+            .json(&serde_json::json!({ "source": source, "destination": destination }));
+        self.send(req).await?;
+
+        Ok(())
+    }
+
+    async fn show_model(&self, model: &str) -> Result<ModelInfo> {
+        let req = self
+            .client
+            .post(self.endpoint("api/show"))
+            .json(&serde_json::json!({ "model": model }));
+        let info: ModelInfo = self.send(req).await?.json().await.map_err(ApiError::from)?;
+
+        Ok(info)
+    }
+
+    async fn refresh_models(&mut self) -> Result<()> {
+        self.models = Some(self.list_models().await?.models);
+        Ok(())
+    }
+
+    fn print_models(
+        &self,
+        output: OutputFormat,
+        sort: ModelSort,
+        filter: Option<&str>,
+    ) -> Result<()> {
+        let models = self.model_listing(sort, filter);
+
+        if output == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&models)?);
+            return Ok(());
+        }
+
+        let mut rows: Vec<[String; 7]> = vec![[
+            "NAME".to_string(),
+            "FAMILY".to_string(),
+            "PARAMS".to_string(),
+            "QUANT".to_string(),
+            "SIZE".to_string(),
+            "MODIFIED".to_string(),
+            "LOADED".to_string(),
+        ]];
+
+        for m in models.iter() {
+            let loaded = if m.loaded {
+                format!(
+                    "{} VRAM until {}",
+                    human_size(m.size_vram.unwrap_or(0) as u64),
+                    m.expires_at.as_deref().map(short_date).unwrap_or_default()
+                )
+            } else {
+                String::new()
+            };
+
+            rows.push([
+                m.name.clone(),
+                m.family.clone(),
+                m.parameter_size.clone().unwrap_or_default(),
+                m.quantization_level.clone().unwrap_or_default(),
+                human_size(m.size as u64),
+                m.modified_at.as_deref().map(short_date).unwrap_or_default(),
+                loaded,
+            ]);
+        }
+
+        print_table(&rows);
+
+        Ok(())
+    }
+
+    fn set_model(&mut self, model: &str) -> Result<()> {
+        /* Make sure model exists */
+        let mut tmp_model = model.to_string();
+
+        if let Some(models) = &self.models {
+            let model_list: Vec<String> = models.iter().map(|v| v.name.clone()).collect();
+
+            if !model_list.contains(&tmp_model) {
+                // Try to append :latest
+                tmp_model += ":latest";
+                if !model_list.contains(&tmp_model) {
+                    return Err(ApiError::ModelNotFound(format!(
+                        "Cannot load model '{}' available models are {:?}",
+                        model, model_list
+                    ))
+                    .into());
+                }
+            }
+        }
+
+        self.current_model = Some(tmp_model);
+
+        Ok(())
+    }
+
+    fn model_names(&self) -> Vec<String> {
+        self.models
+            .iter()
+            .flatten()
+            .map(|m| m.name.clone())
+            .collect()
+    }
+
+    /// Stream the answer of `/api/chat` into `reply`
+    async fn stream(&self, context: &Chat, reply: &mut Reply) -> Result<()> {
+        let res = self
+            .client
+            .post(self.endpoint("api/chat"))
+            .json(context)
+            .send()
+            .await
+            .map_err(ApiError::from)?;
+        let res = ApiError::check(res).await?;
+
+        ndjson::for_each(res, |chat_resp: ChatResponse| {
+            if chat_resp.done {
+                reply.set_stats(chat_resp.stats());
+            }
+
+            reply.push_content(&chat_resp.message.content)?;
+
+            /* Native tool calls are streamed in the message */
+            for call in chat_resp.message.tool_calls.into_iter().flatten() {
+                reply.push_tool_call(call);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+use url::Url;
+
+use crate::backend::{parse_host, Backend};
+use crate::error::ApiError;
+use crate::ollama::{Chat, Message, ModelSort, OutputFormat, Reply, Stats, ToolArgs, ToolCall};
+use crate::render::Echo;
+use crate::sse;
+use crate::table::print_table;
+
+/* llama.cpp server listens on this port by default */
+const DEFAULT_HOST: &str = "localhost:8080";
+
+/* Model from /v1/models
+{
+  "id": "mistral-7b-instruct",
+  "object": "model",
+  "created": 1719916800,
+  "owned_by": "organization-owner"
+}
+*/
+#[derive(Serialize, Deserialize, Debug)]
+struct OpenAiModel {
+    id: String,
+    #[serde(default)]
+    created: Option<i64>,
+    #[serde(default)]
+    owned_by: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiModels {
+    data: Vec<OpenAiModel>,
+}
+
+/* Streamed chunk of /v1/chat/completions
+{
+  "choices": [
+    {
+      "index": 0,
+      "delta": {
+        "content": "Hel",
+        "tool_calls": [
+          {
+            "index": 0,
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "math_calculator", "arguments": "{\"expr" }
+          }
+        ]
+      },
+      "finish_reason": null
+    }
+  ],
+  "usage": { "prompt_tokens": 26, "completion_tokens": 298 }
+}
+*/
+#[derive(Deserialize, Debug)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Delta {
+    content: Option<String>,
+    tool_calls: Option<Vec<DeltaToolCall>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeltaToolCall {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    #[serde(default)]
+    function: DeltaFunction,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DeltaFunction {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/* Tool call whose arguments are streamed as a JSON string */
+#[derive(Default)]
+struct PartialCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+impl PartialCall {
+    fn finish(self) -> ToolCall {
+        let arguments = if self.arguments.trim().is_empty() {
+            Ok(ToolArgs::new())
+        } else {
+            serde_json::from_str::<ToolArgs>(&self.arguments)
+        };
+
+        /* Bad arguments are reported to the model by the tool call */
+        let arguments = arguments.unwrap_or_else(|e| {
+            error!(
+                "Invalid arguments for {} '{}' : {}",
+                self.name, self.arguments, e
+            );
+            ToolArgs::new()
+        });
+
+        ToolCall::new(self.id, &self.name, arguments)
+    }
+}
+
+/// Server implementing the OpenAI chat completions API
+pub struct OpenAi {
+    base: Url,
+    client: reqwest::Client,
+    models: Vec<OpenAiModel>,
+    current_model: Option<String>,
+    echo: Echo,
+}
+
+impl OpenAi {
+    async fn list_models(&self) -> Result<Vec<OpenAiModel>> {
+        let resp = self
+            .client
+            .get(self.endpoint("v1/models"))
+            .send()
+            .await
+            .map_err(ApiError::from)?;
+        let models: OpenAiModels = ApiError::check(resp)
+            .await?
+            .json()
+            .await
+            .map_err(ApiError::from)?;
+
+        Ok(models.data)
+    }
+
+    /// Connect with `client` to the given host or the llama.cpp server default
+    pub async fn new(host: Option<&str>, client: reqwest::Client) -> Result<OpenAi> {
+        let mut base = parse_host(host.unwrap_or(DEFAULT_HOST))?;
+
+        /* Servers are often documented with the "/v1" suffix */
+        if let Some(path) = base.path().strip_suffix("v1/").map(str::to_string) {
+            base.set_path(&path);
+        }
+
+        info!("Connecting to {}", base);
+
+        let mut ret = OpenAi {
+            base,
+            client,
+            models: vec![],
+            current_model: None,
+            echo: Echo::Raw,
+        };
+
+        ret.models = ret.list_models().await?;
+        ret.current_model = ret.models.first().map(|m| m.id.clone());
+
+        Ok(ret)
+    }
+
+    /// Chat request in the OpenAI format
+    fn request(&self, context: &Chat) -> Value {
+        let mut req = json!({
+            "model": context.model(),
+            "messages": messages(context.messages()),
+            "stream": true,
+            "stream_options": { "include_usage": true },
+        });
+
+        if !context.tools().is_empty() {
+            req["tools"] = json!(context.tools());
+        }
+
+        let options = context.options();
+        if let Some(t) = options.temperature {
+            req["temperature"] = json!(t);
+        }
+        if let Some(p) = options.top_p {
+            req["top_p"] = json!(p);
+        }
+        if let Some(seed) = options.seed {
+            req["seed"] = json!(seed);
+        }
+        if let Some(n) = options.num_predict.filter(|n| *n > 0) {
+            req["max_tokens"] = json!(n);
+        }
+        if !options.stop.is_empty() {
+            req["stop"] = json!(options.stop);
+        }
+        if options.num_ctx.is_some() {
+            info!("num_ctx is set on the server with the OpenAI API, ignoring it");
+        }
+
+        match context.format() {
+            Some(Value::String(s)) if s == "json" => {
+                req["response_format"] = json!({ "type": "json_object" });
+            }
+            Some(schema) => {
+                req["response_format"] = json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": "response", "schema": schema },
+                });
+            }
+            None => {}
+        }
+
+        req
+    }
+}
+
+/// Messages in the OpenAI format, where tool results refer to their call
+fn messages(messages: &[Message]) -> Vec<Value> {
+    /* Calls made without an id (Ollama sessions, text tool calls) get one,
+    following tool messages answer them in order */
+    let mut pending: VecDeque<String> = VecDeque::new();
+
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let mut ret = json!({ "role": m.role(), "content": m.content() });
+
+            if !m.tool_calls().is_empty() {
+                pending.clear();
+                let calls: Vec<Value> = m
+                    .tool_calls()
+                    .iter()
+                    .enumerate()
+                    .map(|(j, call)| {
+                        let id = call
+                            .id()
+                            .map(str::to_string)
+                            .unwrap_or(format!("call_{}_{}", i, j));
+                        pending.push_back(id.clone());
+                        json!({
+                            "id": id,
+                            "type": "function",
+                            "function": {
+                                "name": call.name(),
+                                "arguments": serde_json::to_string(call.arguments()).unwrap_or_default(),
+                            },
+                        })
+                    })
+                    .collect();
+                ret["tool_calls"] = json!(calls);
+            }
+
+            if m.role() == "tool" {
+                let id = pending.pop_front();
+                ret["tool_call_id"] = json!(m.tool_call_id().map(str::to_string).or(id));
+            }
+
+            ret
+        })
+        .collect()
+}
+
+impl Backend for OpenAi {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn base(&self) -> &Url {
+        &self.base
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.current_model.as_deref()
+    }
+
+    fn set_model(&mut self, model: &str) -> Result<()> {
+        let names = self.model_names();
+
+        /* Some servers serve a single model and do not list it */
+        if !names.is_empty() && !names.iter().any(|n| n == model) {
+            return Err(ApiError::ModelNotFound(format!(
+                "Cannot load model '{}' available models are {:?}",
+                model, names
+            ))
+            .into());
+        }
+
+        self.current_model = Some(model.to_string());
+
+        Ok(())
+    }
+
+    fn model_names(&self) -> Vec<String> {
+        self.models.iter().map(|m| m.id.clone()).collect()
+    }
+
+    async fn refresh_models(&mut self) -> Result<()> {
+        self.models = self.list_models().await?;
+        Ok(())
+    }
+
+    fn print_models(
+        &self,
+        output: OutputFormat,
+        sort: ModelSort,
+        filter: Option<&str>,
+    ) -> Result<()> {
+        let mut models: Vec<&OpenAiModel> = self
+            .models
+            .iter()
+            .filter(|m| filter.is_none_or(|f| m.id.contains(f)))
+            .collect();
+
+        /* Only names and creation dates are known */
+        match sort {
+            ModelSort::Modified => models.sort_by_key(|m| std::cmp::Reverse(m.created)),
+            _ => models.sort_by(|a, b| a.id.cmp(&b.id)),
+        }
+
+        if output == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&models)?);
+            return Ok(());
+        }
+
+        let mut rows: Vec<[String; 3]> = vec![[
+            "NAME".to_string(),
+            "OWNER".to_string(),
+            "CREATED".to_string(),
+        ]];
+
+        for m in models {
+            rows.push([
+                m.id.clone(),
+                m.owned_by.clone().unwrap_or_default(),
+                m.created
+                    .and_then(|c| chrono::DateTime::from_timestamp(c, 0))
+                    .map(|d| {
+                        d.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default(),
+            ]);
+        }
+
+        print_table(&rows);
+
+        Ok(())
+    }
+
+    fn echo(&self) -> Echo {
+        self.echo.clone()
+    }
+
+    fn set_echo(&mut self, echo: Echo) {
+        self.echo = echo;
+    }
+
+    /// Stream the answer of `/v1/chat/completions` into `reply`
+    async fn stream(&self, context: &Chat, reply: &mut Reply) -> Result<()> {
+        let start = Instant::now();
+        let mut first_token: Option<Instant> = None;
+        let mut calls: BTreeMap<usize, PartialCall> = BTreeMap::new();
+        let mut usage: Option<Usage> = None;
+
+        let res = self
+            .client
+            .post(self.endpoint("v1/chat/completions"))
+            .json(&self.request(context))
+            .send()
+            .await
+            .map_err(ApiError::from)?;
+        let res = ApiError::check(res).await?;
+
+        sse::for_each(res, |chunk: StreamChunk| {
+            if chunk.usage.is_some() {
+                usage = chunk.usage;
+            }
+
+            for choice in chunk.choices {
+                if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                    first_token.get_or_insert_with(Instant::now);
+                    reply.push_content(&content)?;
+                }
+
+                /* Calls are streamed in pieces identified by their index,
+                which comes from the server and may have gaps */
+                for delta in choice.delta.tool_calls.into_iter().flatten() {
+                    first_token.get_or_insert_with(Instant::now);
+                    let call = calls.entry(delta.index).or_default();
+                    if delta.id.is_some() {
+                        call.id = delta.id;
+                    }
+                    call.name += delta.function.name.as_deref().unwrap_or_default();
+                    call.arguments += delta.function.arguments.as_deref().unwrap_or_default();
+                }
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        for call in calls.into_values() {
+            if call.name.trim().is_empty() {
+                error!("Ignoring a tool call without a name");
+                continue;
+            }
+            reply.push_tool_call(call.finish());
+        }
+
+        let end = Instant::now();
+        let usage = usage.unwrap_or(Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+        });
+        reply.set_stats(Stats {
+            requests: 1,
+            prompt_tokens: usage.prompt_tokens,
+            eval_tokens: usage.completion_tokens,
+            eval_duration: end.duration_since(first_token.unwrap_or(end)).as_nanos() as u64,
+            load_duration: 0,
+            total_duration: end.duration_since(start).as_nanos() as u64,
+        });
+
+        Ok(())
+    }
+}
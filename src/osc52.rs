@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/* Most terminals accept at least this much base64 payload (hterm, xterm
+with its default configuration), larger sequences are silently dropped */
+pub const DEFAULT_MAX_BYTES: usize = 100_000;
+
+/* GNU screen drops DCS strings longer than this */
+const SCREEN_CHUNK: usize = 76;
+
+/// Multiplexer the sequence has to go through to reach the terminal
+#[derive(Debug)]
+enum Passthrough {
+    None,
+    Tmux,
+    Screen,
+}
+
+impl Passthrough {
+    /* Only the session variables are trusted, tmux also sets TERM to
+    screen* by default and TERM is forwarded over SSH */
+    fn detect() -> Passthrough {
+        if env::var_os("TMUX").is_some() {
+            Passthrough::Tmux
+        } else if env::var_os("STY").is_some() {
+            Passthrough::Screen
+        } else {
+            Passthrough::None
+        }
+    }
+
+    /// Wrap `seq` so that the multiplexer forwards it as is
+    fn wrap(&self, seq: &str) -> String {
+        match self {
+            Passthrough::None => seq.to_string(),
+            /* Escapes are doubled in tmux DCS passthrough */
+            Passthrough::Tmux => format!("\x1bPtmux;{}\x1b\\", seq.replace('\x1b', "\x1b\x1b")),
+            Passthrough::Screen => {
+                /* The sequence is ASCII, chunks end on char boundaries */
+                let chunks: Vec<&str> = seq
+                    .as_bytes()
+                    .chunks(SCREEN_CHUNK)
+                    .map(|c| std::str::from_utf8(c).unwrap_or_default())
+                    .collect();
+                format!("\x1bP{}\x1b\\", chunks.join("\x1b\\\x1bP"))
+            }
+        }
+    }
+}
+
+/// Outcome of a copy, the text may have been cut to fit the limit
+pub struct Copied {
+    pub copied: usize,
+    pub total: usize,
+}
+
+impl Copied {
+    pub fn truncated(&self) -> bool {
+        self.copied < self.total
+    }
+}
+
+/// Clipboard writer using the OSC 52 terminal escape sequence
+///
+/// Works over SSH as the local terminal sets its own clipboard.
+pub struct Osc52 {
+    max_bytes: usize,
+}
+
+impl Osc52 {
+    /// Limit the base64 payload to `max_bytes`
+    pub fn new(max_bytes: usize) -> Osc52 {
+        Osc52 { max_bytes }
+    }
+
+    /// Longest prefix of `text` whose encoding fits the limit
+    fn fit<'a>(&self, text: &'a str) -> &'a str {
+        /* Base64 turns 3 bytes into 4 */
+        let mut end = (self.max_bytes / 4 * 3).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        &text[..end]
+    }
+
+    fn sequence(&self, text: &str) -> String {
+        let seq = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+        Passthrough::detect().wrap(&seq)
+    }
+
+    /// Send `text` to the controlling terminal clipboard
+    pub fn copy(&self, text: &str) -> Result<Copied> {
+        let fitted = self.fit(text);
+
+        let mut tty = OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .context("No terminal to send the OSC 52 sequence to")?;
+        tty.write_all(self.sequence(fitted).as_bytes())?;
+        tty.flush()?;
+
+        Ok(Copied {
+            copied: fitted.len(),
+            total: text.len(),
+        })
+    }
+
+    /// A terminal is available to receive the sequence
+    pub fn available() -> bool {
+        OpenOptions::new().write(true).open("/dev/tty").is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_expands_three_bytes_to_four() {
+        let osc52 = Osc52::new(8);
+
+        assert_eq!(osc52.fit("abcdefghi"), "abcdef");
+        assert_eq!(STANDARD.encode(osc52.fit("abcdefghi")).len(), 8);
+        assert_eq!(osc52.fit("abc"), "abc");
+        assert_eq!(Osc52::new(3).fit("abc"), "");
+    }
+
+    #[test]
+    fn fit_ends_on_char_boundary() {
+        /* 6 bytes fit, the second é spans bytes 5 and 6 */
+        assert_eq!(Osc52::new(8).fit("abcéé"), "abcé");
+        assert_eq!(Osc52::new(4).fit("éé"), "é");
+    }
+
+    #[test]
+    fn wrap_none() {
+        assert_eq!(
+            Passthrough::None.wrap("\x1b]52;c;YQ==\x07"),
+            "\x1b]52;c;YQ==\x07"
+        );
+    }
+
+    #[test]
+    fn wrap_tmux() {
+        assert_eq!(
+            Passthrough::Tmux.wrap("\x1b]52;c;YQ==\x07"),
+            "\x1bPtmux;\x1b\x1b]52;c;YQ==\x07\x1b\\"
+        );
+    }
+
+    #[test]
+    fn wrap_screen() {
+        assert_eq!(
+            Passthrough::Screen.wrap("\x1b]52;c;YQ==\x07"),
+            "\x1bP\x1b]52;c;YQ==\x07\x1b\\"
+        );
+
+        let seq = "a".repeat(SCREEN_CHUNK + 1);
+        assert_eq!(
+            Passthrough::Screen.wrap(&seq),
+            format!("\x1bP{}\x1b\\\x1bPa\x1b\\", "a".repeat(SCREEN_CHUNK))
+        );
+    }
+}
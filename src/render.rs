@@ -0,0 +1,222 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use termimad::crossterm::cursor::{MoveToColumn, MoveUp};
+use termimad::crossterm::queue;
+use termimad::crossterm::terminal::{self, Clear, ClearType};
+use termimad::MadSkin;
+
+/* Redrawing for each token would flicker with fast models */
+const REDRAW_DELAY: Duration = Duration::from_millis(50);
+
+/// How answers are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Render {
+    /* Markdown rendered as it is streamed */
+    Inline,
+    /* Raw stream, then a markdown pager */
+    Pager,
+    /* Raw stream only */
+    Raw,
+}
+
+/// Where streamed answers go
+#[derive(Clone)]
+pub enum Echo {
+    Off,
+    Raw,
+    Markdown(Box<MadSkin>),
+}
+
+/// Markdown blocks of a streamed text
+struct Blocks<'a> {
+    /// End of the lines which render the same whatever follows
+    stable: usize,
+    /// End of the last complete line
+    complete: usize,
+    /// Opening line of an unterminated code block
+    fence: Option<&'a str>,
+}
+
+impl<'a> Blocks<'a> {
+    /* Markdown is rendered line by line, except code blocks and tables
+    which are laid out as a whole */
+    fn scan(text: &'a str) -> Blocks<'a> {
+        let mut ret = Blocks {
+            stable: 0,
+            complete: 0,
+            fence: None,
+        };
+
+        for line in text.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let start = ret.complete;
+            ret.complete += line.len();
+
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") {
+                ret.fence = match ret.fence {
+                    Some(_) => None,
+                    None => Some(&text[start..ret.complete]),
+                };
+            }
+
+            if ret.fence.is_none() && !trimmed.starts_with('|') {
+                ret.stable = ret.complete;
+            }
+        }
+
+        ret
+    }
+}
+
+/// Prints an answer as it is streamed
+///
+/// In markdown mode finished lines are printed once, the pending ones
+/// (current line, code block or table) are redrawn as tokens arrive.
+pub struct Printer {
+    echo: Echo,
+    text: String,
+    /// Bytes of `text` printed for good
+    done: usize,
+    /// Fence reopening a code block split to fit the screen
+    reopen: String,
+    /// Screen lines of the pending text
+    drawn: u16,
+    last_draw: Option<Instant>,
+}
+
+impl Printer {
+    pub fn new(echo: Echo) -> Printer {
+        Printer {
+            echo,
+            text: String::new(),
+            done: 0,
+            reopen: String::new(),
+            drawn: 0,
+            last_draw: None,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> Result<()> {
+        match self.echo {
+            Echo::Off => Ok(()),
+            Echo::Raw => {
+                print!("{}", text);
+                io::stdout().flush()?;
+                Ok(())
+            }
+            Echo::Markdown(_) => {
+                self.text += text;
+                self.update(false)
+            }
+        }
+    }
+
+    /// Print what is left of the answer
+    pub fn finish(&mut self) -> Result<()> {
+        match self.echo {
+            Echo::Off => Ok(()),
+            Echo::Raw => {
+                println!();
+                Ok(())
+            }
+            Echo::Markdown(_) => self.update(true),
+        }
+    }
+
+    fn pending(&self) -> String {
+        format!("{}{}", self.reopen, &self.text[self.done..])
+    }
+
+    fn update(&mut self, last: bool) -> Result<()> {
+        let pending = self.pending();
+
+        if last {
+            self.draw(&pending, true)?;
+            self.done = self.text.len();
+            self.reopen.clear();
+            return Ok(());
+        }
+
+        let blocks = Blocks::scan(&pending);
+
+        /* Finished lines are printed for good */
+        if blocks.stable > self.reopen.len() {
+            self.draw(&pending[..blocks.stable], true)?;
+            self.done += blocks.stable - self.reopen.len();
+            self.reopen.clear();
+            return self.draw(&self.pending(), false);
+        }
+
+        /* The cursor cannot go above the screen, a block taller than it
+        is split on its last complete line */
+        let (width, height) = screen();
+        if blocks.complete > self.reopen.len() && self.height(&pending, width) + 1 >= height {
+            let mut head = pending[..blocks.complete].to_string();
+            if blocks.fence.is_some() {
+                head += "```\n";
+            }
+            let fence = blocks.fence.unwrap_or_default().to_string();
+            self.draw(&head, true)?;
+            self.done += blocks.complete - self.reopen.len();
+            self.reopen = fence;
+            return self.draw(&self.pending(), false);
+        }
+
+        if self.last_draw.is_some_and(|t| t.elapsed() < REDRAW_DELAY) {
+            return Ok(());
+        }
+
+        self.draw(&pending, false)
+    }
+
+    fn height(&self, md: &str, width: usize) -> usize {
+        match &self.echo {
+            Echo::Markdown(skin) => skin.text(md, Some(width)).lines.len(),
+            _ => 0,
+        }
+    }
+
+    /// Replace the pending lines with `md`, which stays if `keep` is set
+    fn draw(&mut self, md: &str, keep: bool) -> Result<()> {
+        let skin = match &self.echo {
+            Echo::Markdown(skin) => skin,
+            _ => return Ok(()),
+        };
+
+        let text = skin.text(md, Some(screen().0)).to_string();
+
+        let mut out = io::stdout();
+        if self.drawn > 0 {
+            queue!(out, MoveToColumn(0), MoveUp(self.drawn))?;
+        }
+
+        /* tmux pushes the screen to its history when it is cleared from
+        the top-left corner, lines are cleared as they are overwritten */
+        let mut lines = 0;
+        for line in text.lines() {
+            queue!(out, Clear(ClearType::CurrentLine))?;
+            writeln!(out, "{}", line)?;
+            lines += 1;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown))?;
+        out.flush()?;
+
+        self.drawn = if keep { 0 } else { lines };
+        self.last_draw = Some(Instant::now());
+
+        Ok(())
+    }
+}
+
+/// Width and height of the terminal
+fn screen() -> (usize, usize) {
+    terminal::size()
+        .map(|(w, h)| (w as usize, h as usize))
+        .unwrap_or((80, 24))
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ollama::{Chat, Message};
+
+/* Session file
+{
+  "name": "foo",
+  "messages": [
+    { "role": "user", "content": "Hello" },
+    { "role": "assistant", "content": "Hi !" }
+  ]
+}
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    name: String,
+    messages: Vec<Message>,
+}
+
+/// Stores sessions as JSON files in the XDG data dir
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new() -> Result<SessionStore> {
+        let dir = dirs::data_dir()
+            .ok_or(anyhow!("Failed to locate the user data directory"))?
+            .join("clippyrs")
+            .join("sessions");
+
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create session directory {}", dir.display()))?;
+
+        Ok(SessionStore { dir })
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(anyhow!("Invalid session name '{}'", name));
+        }
+
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut ret: Vec<String> = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                if let Some(name) = path.file_stem() {
+                    ret.push(name.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        ret.sort();
+
+        Ok(ret)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Option<Session>> {
+        let path = self.path(name)?;
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read session {}", path.display()))?;
+        let session: Session = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse session {}", path.display()))?;
+
+        Ok(Some(session))
+    }
+
+    pub fn save(&self, session: &Session) -> Result<()> {
+        let path = self.path(&session.name)?;
+        let data = serde_json::to_string_pretty(session)?;
+
+        fs::write(&path, data)
+            .with_context(|| format!("Failed to write session {}", path.display()))?;
+        info!("Saved session '{}' to {}", session.name, path.display());
+
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+
+        if !path.exists() {
+            return Err(anyhow!("No such session '{}'", name));
+        }
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    /// Load session messages in the chat, a new session starts empty
+    pub fn restore(&self, name: &str, chat: &mut Chat) -> Result<()> {
+        if let Some(session) = self.load(name)? {
+            info!(
+                "Restored {} messages from session '{}'",
+                session.messages.len(),
+                name
+            );
+            chat.set_messages(session.messages);
+        }
+
+        Ok(())
+    }
+
+    /// Save the current chat messages in the session
+    pub fn store(&self, name: &str, chat: &Chat) -> Result<()> {
+        self.save(&Session {
+            name: name.to_string(),
+            messages: chat.messages().clone(),
+        })
+    }
+
+    /// Render a session as a markdown transcript
+    pub fn export(&self, name: &str) -> Result<String> {
+        let session = self
+            .load(name)?
+            .ok_or(anyhow!("No such session '{}'", name))?;
+
+        let mut ret = format!("# Session {}\n", session.name);
+
+        for m in session.messages.iter() {
+            ret += &format!("\n## {}\n\n{}\n", m.role(), m.content());
+        }
+
+        Ok(ret)
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+
+use crate::error::ApiError;
+use crate::ndjson::{parse_line, LineBuffer};
+
+/* Server-sent events as streamed by OpenAI compatible servers
+
+data: {"choices":[{"delta":{"content":"Hel"}}]}
+
+data: [DONE]
+*/
+const DONE: &str = "[DONE]";
+
+/// Returns false once the end of stream marker is reached
+fn parse_event<T: DeserializeOwned>(
+    line: &str,
+    f: &mut impl FnMut(T) -> Result<()>,
+) -> Result<bool> {
+    /* Event names, ids and comments are not used, each data line
+    holds a full JSON object with these servers */
+    let data = match line.strip_prefix("data:") {
+        Some(data) => data.trim(),
+        None => return Ok(true),
+    };
+
+    if data == DONE {
+        return Ok(false);
+    }
+
+    parse_line(data, f)?;
+
+    Ok(true)
+}
+
+/// Call `f` for the JSON data of each event of a streamed response
+pub async fn for_each<T: DeserializeOwned>(
+    resp: reqwest::Response,
+    mut f: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    let mut lines = LineBuffer::default();
+    let mut stream = resp.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(ApiError::from_stream)?;
+        for line in lines.push(&chunk)? {
+            if !parse_event(&line, &mut f)? {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(line) = lines.finish()? {
+        parse_event(&line, &mut f)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn data_event() {
+        let mut events: Vec<Value> = Vec::new();
+
+        let more = parse_event("data: {\"choices\": []}", &mut |v| {
+            events.push(v);
+            Ok(())
+        })
+        .unwrap();
+        assert!(more);
+        assert_eq!(events, vec![serde_json::json!({"choices": []})]);
+    }
+
+    #[test]
+    fn done_event() {
+        let more = parse_event("data: [DONE]", &mut |_: Value| {
+            panic!("[DONE] is not an event")
+        })
+        .unwrap();
+        assert!(!more);
+    }
+
+    #[test]
+    fn other_lines_are_skipped() {
+        let more = parse_event(": keepalive", &mut |_: Value| panic!("not an event")).unwrap();
+        assert!(more);
+    }
+
+    #[test]
+    fn error_event() {
+        let err = parse_event(
+            "data: {\"error\": {\"message\": \"context length exceeded\"}}",
+            &mut |_: Value| Ok(()),
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<ApiError>(),
+            Some(ApiError::StreamInterrupted(msg)) if msg == "context length exceeded"
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+/// Print rows as left aligned columns, the first row being the header
+pub fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows.iter() {
+        for (w, col) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(col.chars().count());
+        }
+    }
+
+    for row in rows.iter() {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(col, w)| format!("{:<w$}", col, w = w))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Format a size in bytes with a binary unit
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
@@ -0,0 +1,424 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use crate::clipboard::{Clipboard, ClipboardBackend};
+use crate::editor::confirm;
+
+/// A prompt variable written `::NAME::` or `::NAME:argument::`
+pub trait Expander {
+    fn name(&self) -> &str;
+    fn expand(&self, arg: Option<&str>) -> Result<String>;
+}
+
+/// Expands registered variables in prompts
+pub struct Unfolder {
+    expanders: Vec<Box<dyn Expander>>,
+}
+
+impl Unfolder {
+    fn new() -> Unfolder {
+        Unfolder { expanders: vec![] }
+    }
+
+    /// All builtin variables, `::CMD::` asks before running unless `confirm` is false
+    pub fn with_defaults(confirm: bool, clipboard: ClipboardBackend) -> Unfolder {
+        let mut ret = Unfolder::new();
+        ret.register(Box::new(ClipboardVar { clipboard }));
+        ret.register(Box::new(Selection {}));
+        ret.register(Box::new(FileVar {}));
+        ret.register(Box::new(EnvVar {}));
+        ret.register(Box::new(DateVar {}));
+        ret.register(Box::new(CmdVar { confirm }));
+        ret.register(Box::new(UrlVar {}));
+        ret
+    }
+
+    pub fn register(&mut self, expander: Box<dyn Expander>) {
+        self.expanders.push(expander);
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn Expander> {
+        self.expanders
+            .iter()
+            .find(|e| e.name() == name)
+            .map(|e| e.as_ref())
+    }
+
+    /// Replace all known variables, unknown ones are left as is
+    pub fn unfold(&self, prompt: &str) -> Result<String> {
+        let mut ret = String::new();
+        let mut rest = prompt;
+
+        while let Some(start) = rest.find("::") {
+            ret += &rest[..start];
+            let after = &rest[start + 2..];
+
+            /* Variable names are uppercase */
+            let name_len = after
+                .find(|c: char| !c.is_ascii_uppercase())
+                .unwrap_or(after.len());
+            let name = &after[..name_len];
+            let tail = &after[name_len..];
+
+            let parsed = if name.is_empty() {
+                None
+            } else if let Some(tail) = tail.strip_prefix("::") {
+                Some((None, tail))
+            } else if let Some(tail) = tail.strip_prefix(':') {
+                tail.find("::")
+                    .map(|end| (Some(&tail[..end]), &tail[end + 2..]))
+            } else {
+                None
+            };
+
+            match (parsed, self.get(name)) {
+                (Some((arg, tail)), Some(expander)) => {
+                    let value = expander.expand(arg).with_context(|| {
+                        format!(
+                            "Failed to expand ::{}{}::",
+                            name,
+                            arg.map(|a| format!(":{}", a)).unwrap_or_default()
+                        )
+                    })?;
+                    ret += &value;
+                    rest = tail;
+                }
+                _ => {
+                    ret += "::";
+                    rest = after;
+                }
+            }
+        }
+
+        ret += rest;
+
+        Ok(ret)
+    }
+}
+
+fn no_arg(name: &str, arg: Option<&str>) -> Result<()> {
+    if arg.is_some() {
+        return Err(anyhow!("::{}:: takes no argument", name));
+    }
+    Ok(())
+}
+
+fn need_arg<'a>(name: &str, arg: Option<&'a str>) -> Result<&'a str> {
+    arg.filter(|a| !a.is_empty()).ok_or(anyhow!(
+        "::{}:: needs an argument as in ::{}:value::",
+        name,
+        name
+    ))
+}
+
+/// `::CL::` the clipboard content
+struct ClipboardVar {
+    clipboard: ClipboardBackend,
+}
+
+impl Expander for ClipboardVar {
+    fn name(&self) -> &str {
+        "CL"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        no_arg(self.name(), arg)?;
+        Clipboard::new(self.clipboard).get()
+    }
+}
+
+/// `::SEL::` the primary selection
+struct Selection {}
+
+impl Expander for Selection {
+    fn name(&self) -> &str {
+        "SEL"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        no_arg(self.name(), arg)?;
+
+        let candidates: [(&str, &[&str]); 3] = [
+            ("wl-paste", &["--primary", "--no-newline"]),
+            ("xclip", &["-selection", "primary", "-out"]),
+            ("xsel", &["--primary", "--output"]),
+        ];
+
+        for (bin, args) in candidates.iter() {
+            if let Ok(out) = Command::new(bin).args(args.iter()).output() {
+                if out.status.success() {
+                    return Ok(String::from_utf8_lossy(&out.stdout).to_string());
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "Failed to read primary selection (needs wl-paste, xclip or xsel)"
+        ))
+    }
+}
+
+/// `::FILE:path::` the content of a file
+struct FileVar {}
+
+impl Expander for FileVar {
+    fn name(&self) -> &str {
+        "FILE"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        let path = need_arg(self.name(), arg)?;
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))
+    }
+}
+
+/// `::ENV:VAR::` an environment variable
+struct EnvVar {}
+
+impl Expander for EnvVar {
+    fn name(&self) -> &str {
+        "ENV"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        let var = need_arg(self.name(), arg)?;
+        env::var(var).map_err(|e| anyhow!("{} : {}", var, e))
+    }
+}
+
+/// `::DATE::` or `::DATE:format::` the current local date
+struct DateVar {}
+
+impl Expander for DateVar {
+    fn name(&self) -> &str {
+        "DATE"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        let fmt = arg.unwrap_or("%Y-%m-%d");
+
+        /* Formatting panics on invalid specifiers */
+        let items: Vec<Item> = StrftimeItems::new(fmt).collect();
+        if items.contains(&Item::Error) {
+            return Err(anyhow!("Invalid date format '{}'", fmt));
+        }
+
+        Ok(chrono::Local::now()
+            .format_with_items(items.into_iter())
+            .to_string())
+    }
+}
+
+/// `::CMD:command::` the output of a shell command
+struct CmdVar {
+    confirm: bool,
+}
+
+impl Expander for CmdVar {
+    fn name(&self) -> &str {
+        "CMD"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        let cmd = need_arg(self.name(), arg)?;
+
+        if self.confirm && !confirm(&format!("Run `{}` to expand ::CMD:: ?", cmd))? {
+            return Err(anyhow!("Command refused by user"));
+        }
+
+        let out = Command::new("sh").arg("-c").arg(cmd).output()?;
+
+        if !out.status.success() {
+            return Err(anyhow!(
+                "Command failed with {} : {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+
+        /* Like shell substitution drop trailing newlines */
+        Ok(String::from_utf8_lossy(&out.stdout)
+            .trim_end_matches('\n')
+            .to_string())
+    }
+}
+
+/// `::URL:address::` the text of a web page
+struct UrlVar {}
+
+impl Expander for UrlVar {
+    fn name(&self) -> &str {
+        "URL"
+    }
+
+    fn expand(&self, arg: Option<&str>) -> Result<String> {
+        let url = need_arg(self.name(), arg)?;
+
+        /* Prompts are unfolded from the async runtime */
+        let body = tokio::task::block_in_place(|| -> Result<String> {
+            let resp = reqwest::blocking::get(url)?.error_for_status()?;
+            Ok(resp.text()?)
+        })?;
+
+        Ok(html_to_text(&body))
+    }
+}
+
+/// Crude HTML to text conversion dropping tags, scripts and styles
+fn html_to_text(html: &str) -> String {
+    let mut ret = String::new();
+    /* ASCII lowercasing keeps byte offsets */
+    let lower = html.to_ascii_lowercase();
+    let mut pos = 0;
+
+    while let Some(start) = html[pos..].find('<').map(|s| s + pos) {
+        ret += &html[pos..start];
+
+        let tag = &lower[start..];
+        let skip_to = if tag.starts_with("<script") {
+            tag.find("</script>").map(|e| e + 9)
+        } else if tag.starts_with("<style") {
+            tag.find("</style>").map(|e| e + 8)
+        } else {
+            tag.find('>').map(|e| e + 1)
+        };
+
+        match skip_to {
+            Some(end) => {
+                ret.push(' ');
+                pos = start + end;
+            }
+            None => {
+                pos = html.len();
+            }
+        }
+    }
+
+    ret += &html[pos..];
+
+    let ret = ret
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    ret.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `::ECHO:text::` expands to its argument
+    struct Echo {}
+
+    impl Expander for Echo {
+        fn name(&self) -> &str {
+            "ECHO"
+        }
+
+        fn expand(&self, arg: Option<&str>) -> Result<String> {
+            Ok(arg.unwrap_or_default().to_string())
+        }
+    }
+
+    /// `::PASTE::` content holding a variable, as a clipboard could
+    struct Paste {}
+
+    impl Expander for Paste {
+        fn name(&self) -> &str {
+            "PASTE"
+        }
+
+        fn expand(&self, _: Option<&str>) -> Result<String> {
+            Ok("run ::BOOM:: now".to_string())
+        }
+    }
+
+    /// `::BOOM::` must never be expanded
+    struct Boom {}
+
+    impl Expander for Boom {
+        fn name(&self) -> &str {
+            "BOOM"
+        }
+
+        fn expand(&self, _: Option<&str>) -> Result<String> {
+            panic!("::BOOM:: was expanded")
+        }
+    }
+
+    fn unfolder() -> Unfolder {
+        let mut ret = Unfolder::new();
+        ret.register(Box::new(Echo {}));
+        ret.register(Box::new(Paste {}));
+        ret.register(Box::new(Boom {}));
+        ret.register(Box::new(DateVar {}));
+        ret.register(Box::new(FileVar {}));
+        ret
+    }
+
+    #[test]
+    fn known_variables_expanded() {
+        assert_eq!(
+            unfolder().unfold("a ::ECHO:b:: c ::ECHO::").unwrap(),
+            "a b c "
+        );
+    }
+
+    #[test]
+    fn unknown_variables_kept() {
+        let prompt = "::NOPE:: ::NOPE:x:: ::echo:: :: a::b ::ECHO ::ECHO:x";
+        assert_eq!(unfolder().unfold(prompt).unwrap(), prompt);
+    }
+
+    #[test]
+    fn unterminated_argument_kept() {
+        assert_eq!(unfolder().unfold("see ::FILE:x").unwrap(), "see ::FILE:x");
+    }
+
+    #[test]
+    fn expanded_values_not_rescanned() {
+        assert_eq!(
+            unfolder().unfold("::PASTE::, ::PASTE::").unwrap(),
+            "run ::BOOM:: now, run ::BOOM:: now"
+        );
+    }
+
+    #[test]
+    fn date_with_colons() {
+        let date = unfolder().unfold("::DATE:%H:%M::").unwrap();
+
+        assert_eq!(date.len(), 5, "{}", date);
+        assert_eq!(&date[2..3], ":");
+        assert!(date.replace(':', "").chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn invalid_date_format() {
+        assert!(unfolder().unfold("::DATE:%Q::").is_err());
+    }
+
+    #[test]
+    fn html_tags_stripped() {
+        let html = "<HTML><Script type=\"x\">alert('<b>')</SCRIPT><style>p {}</style>\
+            <p>Fish &amp; chips</p>\n\n<P>a &lt;b&gt;</P></html>";
+
+        assert_eq!(html_to_text(html), "Fish & chips\na <b>");
+    }
+
+    #[test]
+    fn html_unterminated_tag() {
+        assert_eq!(html_to_text("text <script>never closed"), "text");
+        assert_eq!(html_to_text("é <b"), "é");
+    }
+}